@@ -0,0 +1,37 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, Response, StatusCode},
+    middleware::Next,
+};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+use crate::{json_error, AppState};
+
+/// Reject requests without `Authorization: Bearer <token>` when an auth
+/// token is configured. Only applied to routes that modify team files.
+pub async fn require_token(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let Some(token) = &state.config.auth_token else {
+        return next.run(request).await;
+    };
+
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // Constant time, so response times don't reveal how much of the
+        // token was right
+        .is_some_and(|provided| provided.as_bytes().ct_eq(token.as_bytes()).into());
+
+    if authorized {
+        next.run(request).await
+    } else {
+        json_error(StatusCode::UNAUTHORIZED, "Missing or invalid auth token")
+    }
+}
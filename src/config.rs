@@ -0,0 +1,277 @@
+use axum::http::HeaderValue;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use crate::{sprites::SPRITE_EXTENSIONS, spritesheet};
+
+/// Serve a live Pokemon team overlay for OBS browser sources.
+#[derive(Clone, Debug, Parser)]
+#[command(version)]
+pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Address to listen on (use 0.0.0.0 or :: to allow other machines on the
+    /// LAN). Repeat to listen on several addresses, e.g. both IPv4 and IPv6.
+    #[arg(long, default_values_t = [IpAddr::V4(Ipv4Addr::LOCALHOST)])]
+    pub host: Vec<IpAddr>,
+
+    /// Port to listen on
+    #[arg(long, default_value_t = 3000, value_parser = clap::value_parser!(u16).range(1..))]
+    pub port: u16,
+
+    /// Directory containing the team files
+    #[arg(long, env = "TEAM_DIR", default_value = ".")]
+    pub team_dir: PathBuf,
+
+    /// Comma-separated list of team files to read, instead of every file with
+    /// "team" in its name
+    #[arg(long, value_delimiter = ',')]
+    pub team_files: Vec<String>,
+
+    /// Also read team files in sub-directories of the team directory. Their
+    /// key is the relative path joined with `-`, e.g. `runs-2024-team`
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// Write the default roster to team.txt if it is empty, instead of only
+    /// warning about it
+    #[arg(long)]
+    pub repopulate_empty: bool,
+
+    /// Comma-separated roster written to team.txt when it doesn't exist (or
+    /// is empty, with --repopulate-empty)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_values_t = ["pikachu", "charizard", "blastoise", "venusaur", "mewtwo", "dragonite"]
+            .map(String::from)
+    )]
+    pub default_team: Vec<String>,
+
+    /// Read several comma-separated Pokemon per line in plain team files,
+    /// e.g. `pikachu, charizard:Char, blastoise`
+    #[arg(long)]
+    pub csv_line: bool,
+
+    /// Number of Pokemon slots per team
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u16).range(1..))]
+    pub team_size: u16,
+
+    /// Only send the Pokemon listed in the file instead of padding with empty slots
+    #[arg(long)]
+    pub no_pad: bool,
+
+    /// When there is exactly one team, send just that team instead of a map
+    /// keyed by team name: at the root of `/teams`, and at the root of the
+    /// WebSocket and SSE `full_sync` messages, which then replace deltas.
+    /// Several teams are still sent as a map
+    #[arg(long)]
+    pub single_team: bool,
+
+    /// Order teams are sent in
+    #[arg(long, value_enum, default_value_t = TeamOrder::Name)]
+    pub sort_teams: TeamOrder,
+
+    /// URL search param the overlay reads the team name from
+    #[arg(long, default_value = "team")]
+    pub team_param: String,
+
+    /// Download missing sprites from PokeAPI when teams are loaded
+    #[arg(long)]
+    pub fetch_sprites: bool,
+
+    /// Also watch the sprites directory and tell clients to reload sprites
+    /// that change
+    #[arg(long)]
+    pub watch_sprites: bool,
+
+    /// Order to pick sprite files in when a Pokemon has several, e.g.
+    /// `gif,png` so animated sprites win. By default the extension the
+    /// overlay asked for is used if it exists, then png, gif, jpg and webp
+    #[arg(long, value_delimiter = ',', value_parser = parse_sprite_ext)]
+    pub sprite_ext_order: Vec<String>,
+
+    /// Serve a team's sprites from another directory, as `team=dir`.
+    /// Sprites missing there fall back to the sprites directory
+    #[arg(long, value_delimiter = ',', value_parser = parse_sprites_map)]
+    pub sprites_map: Vec<(String, PathBuf)>,
+
+    /// Only log warnings and errors to the console, skipping the startup
+    /// banner. Takes precedence over RUST_LOG
+    #[arg(long, short)]
+    pub quiet: bool,
+
+    /// Also write logs to this file, rotated daily with the date added to
+    /// the name (`overlay.log` becomes `overlay.2024-05-01.log`). Handy to
+    /// attach to bug reports
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// Open the overlay in the default browser once the server is up
+    #[arg(long)]
+    pub open: bool,
+
+    /// Serve the overlay from the static directory on disk instead of the
+    /// copy embedded in the binary, so edits show up without recompiling
+    #[arg(long)]
+    pub dev: bool,
+
+    /// Only serve the API (`/ws`, `/teams`, sprites...) and answer 404 for
+    /// every other path instead of serving the overlay, for setups where a
+    /// reverse proxy hosts the frontend separately
+    #[arg(long, conflicts_with_all = ["dev", "open"])]
+    pub api_only: bool,
+
+    /// Serve Prometheus metrics on `/metrics`: broadcasts sent, connected
+    /// clients, team file read errors and how long reading the teams takes
+    #[arg(long)]
+    pub metrics: bool,
+
+    /// Wait this long without new file events before re-reading team files
+    #[arg(long, default_value_t = 150)]
+    pub debounce_ms: u64,
+
+    /// Re-read a changed team file after this long and only broadcast once
+    /// two reads agree, so half-written files aren't sent
+    #[arg(long, default_value_t = 50)]
+    pub write_settle_ms: u64,
+
+    /// Updates kept queued for slow clients. A client that falls further
+    /// behind skips them and gets a full resync instead. Higher values hold
+    /// on to more old updates in memory but resync lagging clients less often
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    pub channel_capacity: u32,
+
+    /// PEM certificate to serve HTTPS with, together with --tls-key
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Accept at most this many requests per second on routes that modify
+    /// team files, answering 429 to the rest
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_writes_per_sec: u32,
+
+    /// Only allow cross-origin requests from this origin (e.g.
+    /// `http://192.168.1.20:8080`). Repeat to allow several. Any origin is
+    /// allowed when none is given
+    #[arg(long, value_parser = parse_cors_origin)]
+    pub cors_origin: Vec<HeaderValue>,
+
+    /// Reject request bodies larger than this many bytes with 413 on routes
+    /// that modify team files
+    #[arg(long, default_value_t = 64 * 1024)]
+    pub max_body_size: usize,
+
+    /// Require `Authorization: Bearer <token>` on routes that modify team files
+    /// or force a reload
+    #[arg(long, env = "AUTH_TOKEN")]
+    pub auth_token: Option<String>,
+}
+
+/// Settings the overlay frontend needs, served from `/config`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientConfig {
+    pub team_size: u16,
+    pub padded: bool,
+    pub sprites_url: String,
+    pub team_param: String,
+    /// Sprite base URL for teams with their own sprite directory
+    pub team_sprites_urls: HashMap<String, String>,
+    /// Width and height of each slot in `/teams/{name}/spritesheet.png`
+    pub spritesheet_cell_size: u32,
+}
+
+impl Config {
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.host
+            .iter()
+            .map(|&host| SocketAddr::new(host, self.port))
+            .collect()
+    }
+
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig {
+            team_size: self.team_size,
+            padded: !self.no_pad,
+            sprites_url: "/sprites".to_string(),
+            team_param: self.team_param.clone(),
+            team_sprites_urls: self
+                .sprites_map
+                .iter()
+                .map(|(team, _)| (team.clone(), format!("/team-sprites/{}", team)))
+                .collect(),
+            spritesheet_cell_size: spritesheet::CELL_SIZE,
+        }
+    }
+
+    /// Sprite directory configured for a team with --sprites-map.
+    pub fn team_sprites_dir(&self, team: &str) -> Option<&Path> {
+        self.sprites_map
+            .iter()
+            .find(|(name, _)| name == team)
+            .map(|(_, dir)| dir.as_path())
+    }
+}
+
+/// How teams are ordered in payloads, see --sort-teams.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TeamOrder {
+    /// Alphabetically by key
+    Name,
+    /// Most recently modified file first
+    Mtime,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Parse a team file like the server does and check every Pokemon has a
+    /// sprite, without starting the server
+    Validate {
+        /// Team file to check
+        file: PathBuf,
+    },
+}
+
+fn parse_sprites_map(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((team, dir)) if !team.is_empty() && !dir.is_empty() => {
+            Ok((team.to_string(), PathBuf::from(dir)))
+        }
+        _ => Err(format!("expected team=dir, got '{}'", value)),
+    }
+}
+
+fn parse_cors_origin(value: &str) -> Result<HeaderValue, String> {
+    // Browsers send the origin without a trailing slash
+    let origin = value.trim_end_matches('/');
+    if !origin.starts_with("http://") && !origin.starts_with("https://") {
+        return Err(format!(
+            "expected an http:// or https:// origin, got '{}'",
+            value
+        ));
+    }
+    HeaderValue::from_str(origin).map_err(|e| e.to_string())
+}
+
+fn parse_sprite_ext(value: &str) -> Result<String, String> {
+    let ext = value.trim().trim_start_matches('.').to_lowercase();
+    if SPRITE_EXTENSIONS.contains(&ext.as_str()) {
+        Ok(ext)
+    } else {
+        Err(format!(
+            "unknown sprite extension '{}', expected one of {}",
+            value,
+            SPRITE_EXTENSIONS.join(", ")
+        ))
+    }
+}
@@ -1,28 +1,72 @@
+mod auth;
+mod config;
+mod metrics;
+mod pokeapi;
+mod rate_limit;
+mod showdown;
+mod sprites;
+mod spritesheet;
+mod types;
 mod utils;
 
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        Path, Query, State,
     },
-    http::{header, Response, StatusCode},
-    response::IntoResponse,
-    routing::get,
-    Router,
+    http::{header, HeaderMap, Response, StatusCode},
+    middleware,
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{get, post},
+    Json, Router,
 };
-use futures::{SinkExt, StreamExt};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use config::{ClientConfig, Command, Config, TeamOrder};
+use futures::{FutureExt, SinkExt, Stream, StreamExt};
+use ignore::gitignore::Gitignore;
+use indexmap::IndexMap;
+use metrics::Metrics;
 use notify::{Event, RecursiveMode, Watcher};
+use rate_limit::RateLimiter;
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
+use sprites::SpriteAliases;
 use std::{
-    collections::HashMap,
-    fs::{self, DirEntry},
+    borrow::Cow,
+    collections::HashSet,
+    convert::Infallible,
+    fs,
+    future::{Future, IntoFuture},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     path,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch, Notify,
+};
+use tokio::time::{timeout_at, Duration, Instant};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate},
+        CompressionLayer, DefaultPredicate,
+    },
+    cors::{AllowOrigin, CorsLayer},
+    limit::RequestBodyLimitLayer,
 };
-use tokio::sync::broadcast;
-use tower_http::{cors::CorsLayer, services::ServeDir};
+use tracing::{debug, error, info, trace, warn};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 
 // --------------------
 // Pack static assets into the binary
@@ -34,268 +78,2587 @@ struct Assets;
 const TEAM_FILE: &str = "team.txt";
 const SPRITES_DIR: &str = "sprites";
 const STATIC_DIR: &str = "static";
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest a burst of file events is collected for, in debounce windows
+const MAX_BURST_WINDOWS: u32 = 10;
+const STATUS_CONDITIONS: [&str; 7] = ["PAR", "PSN", "TOX", "BRN", "SLP", "FRZ", "FNT"];
+/// Largest sprite that can be embedded in a team file, once decoded
+const MAX_INLINE_SPRITE_SIZE: usize = 256 * 1024;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 struct Pokemon {
     name: String,
     nickname: Option<String>,
+    item: Option<String>,
+    ability: Option<String>,
+    level: Option<u8>,
+    gender: Option<char>,
+    shiny: Option<bool>,
+    hp_current: Option<u16>,
+    hp_max: Option<u16>,
+    /// Status condition such as `PAR` or `FNT`
+    status: Option<String>,
+    nature: Option<String>,
+    #[serde(default)]
+    moves: Vec<String>,
+    evs: Option<Stats>,
+    ivs: Option<Stats>,
+    /// Lowercase type names such as `fire`. Filled in from the bundled
+    /// table when the team file doesn't list them.
+    #[serde(default)]
+    types: Vec<String>,
+    /// Set when the name has characters no Pokemon name has, so overlays
+    /// can point out the malformed line
+    #[serde(default, skip_deserializing)]
+    suspicious_name: bool,
+    /// Where to load the sprite from, the placeholder if there is none
+    #[serde(default, skip_deserializing)]
+    sprite_url: Option<String>,
+    /// Sprite embedded in the team file as a data URL, used instead of
+    /// `sprite_url`
+    sprite_data: Option<String>,
+    /// Set on the slots added to pad a team to `--team-size`, so overlays
+    /// can tell them apart from Pokemon listed in the file
+    #[serde(default, skip_deserializing)]
+    empty: bool,
+}
+
+/// EV or IV spread. Stats left out of the team file are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Stats {
+    hp: Option<u8>,
+    atk: Option<u8>,
+    def: Option<u8>,
+    spa: Option<u8>,
+    spd: Option<u8>,
+    spe: Option<u8>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Teams by key, in the order they are sent (see --sort-teams).
+type Teams = IndexMap<String, PokemonTeam>;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct PokemonTeam {
     pokemon: Vec<Pokemon>,
+    /// When the team file was last modified, in unix seconds
+    #[serde(default, skip_deserializing)]
+    modified_at: Option<u64>,
+}
+
+/// Message pushed to overlay clients, tagged with a `type` field
+/// (`full_sync`, `delta`, `reload_sprite` or `error`) so new kinds can be
+/// added without confusing older clients.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMsg {
+    /// Every team, sent on connect
+    FullSync { teams: Arc<Teams> },
+    /// A single team whose file changed
+    Delta { team: String, data: PokemonTeam },
+    /// A sprite file changed, so clients should bypass their cached copy
+    ReloadSprite { name: String },
+    /// The teams couldn't be read, so overlays can say why nothing shows
+    Error { message: String },
+}
+
+/// A `ServerMsg` along with the time the teams were last updated (unix
+/// millis), as sent to clients.
+#[derive(Serialize)]
+struct Payload<'a, T> {
+    #[serde(flatten)]
+    update: &'a T,
+    updated_at: u64,
+}
+
+/// A full sync of a single team, with the team at the root instead of in a
+/// one-key map (`--single-team`).
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "full_sync")]
+struct SingleTeamSync<'a> {
+    #[serde(flatten)]
+    team: &'a PokemonTeam,
+}
+
+impl ServerMsg {
+    /// The team to send at the root of a full sync in `--single-team` mode.
+    fn single_team(&self, single_team: bool) -> Option<SingleTeamSync<'_>> {
+        match self {
+            ServerMsg::FullSync { teams } if single_team && teams.len() == 1 => {
+                teams.values().next().map(|team| SingleTeamSync { team })
+            }
+            _ => None,
+        }
+    }
+
+    fn to_json(&self, updated_at: u64, single_team: bool) -> Arc<str> {
+        let json = match self.single_team(single_team) {
+            Some(update) => serde_json::to_string(&Payload {
+                update: &update,
+                updated_at,
+            }),
+            None => serde_json::to_string(&Payload {
+                update: self,
+                updated_at,
+            }),
+        };
+        json.unwrap().into()
+    }
+
+    /// Same as `to_json` but MessagePack encoded, with field names kept.
+    fn to_msgpack(&self, updated_at: u64, single_team: bool) -> Vec<u8> {
+        let msgpack = match self.single_team(single_team) {
+            Some(update) => rmp_serde::to_vec_named(&Payload {
+                update: &update,
+                updated_at,
+            }),
+            None => rmp_serde::to_vec_named(&Payload {
+                update: self,
+                updated_at,
+            }),
+        };
+        msgpack.unwrap()
+    }
+}
+
+/// A `ServerMsg` serialized once so every connected client can share the
+/// same buffer.
+#[derive(Clone, Debug)]
+struct TeamBroadcast {
+    update: Arc<ServerMsg>,
+    updated_at: u64,
+    /// Position in the broadcast sequence, see `AppState::broadcast`
+    seq: u64,
+    /// Whether a full sync of one team is sent as just that team
+    single_team: bool,
+    json: Arc<str>,
+}
+
+impl TeamBroadcast {
+    fn new(update: ServerMsg, updated_at: u64, seq: u64, single_team: bool) -> Self {
+        let json = update.to_json(updated_at, single_team);
+        Self {
+            update: Arc::new(update),
+            updated_at,
+            seq,
+            single_team,
+            json,
+        }
+    }
+}
+
+impl TeamBroadcast {
+    /// The update for a client subscribed to `subscription`, or `None` if it
+    /// doesn't concern it. Borrowed when the client gets the whole update.
+    fn update_for(&self, subscription: Option<&str>) -> Option<Cow<'_, ServerMsg>> {
+        let Some(subscribed) = subscription else {
+            return Some(Cow::Borrowed(&self.update));
+        };
+
+        match &*self.update {
+            ServerMsg::Delta { team, .. } if team == subscribed => {
+                Some(Cow::Borrowed(&self.update))
+            }
+            ServerMsg::Delta { .. } => None,
+            ServerMsg::ReloadSprite { .. } | ServerMsg::Error { .. } => {
+                Some(Cow::Borrowed(&self.update))
+            }
+            ServerMsg::FullSync { teams } => {
+                let team = teams
+                    .get_key_value(subscribed)
+                    .map(|(name, team)| (name.clone(), team.clone()));
+                Some(Cow::Owned(ServerMsg::FullSync {
+                    teams: Arc::new(team.into_iter().collect()),
+                }))
+            }
+        }
+    }
+
+    /// JSON payload for a client subscribed to `subscription`, reusing the
+    /// shared buffer when possible.
+    fn json_for(&self, subscription: Option<&str>) -> Option<Arc<str>> {
+        match self.update_for(subscription)? {
+            Cow::Borrowed(_) => Some(self.json.clone()),
+            Cow::Owned(update) => Some(update.to_json(self.updated_at, self.single_team)),
+        }
+    }
+
+    /// WebSocket message for a client, in the encoding it asked for.
+    fn message_for(&self, subscription: Option<&str>, encoding: Encoding) -> Option<Message> {
+        match encoding {
+            Encoding::Json => {
+                let json = self.json_for(subscription)?;
+                Some(Message::Text(json.to_string()))
+            }
+            Encoding::MsgPack => {
+                let update = self.update_for(subscription)?;
+                Some(Message::Binary(
+                    update.to_msgpack(self.updated_at, self.single_team),
+                ))
+            }
+        }
+    }
+}
+
+/// How messages are encoded on a WebSocket, picked with `/ws?enc=...`.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    /// Text messages, the default
+    #[default]
+    Json,
+    /// Binary MessagePack messages, for bandwidth-constrained overlays
+    MsgPack,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsParams {
+    #[serde(default)]
+    enc: Encoding,
+}
+
+/// Messages overlay clients can send over the WebSocket.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ClientMsg {
+    /// Only receive updates for this team from now on
+    Subscribe(String),
+    /// Send this team's current state right away, as a `delta`
+    Get(String),
 }
 
 struct AppState {
-    tx: broadcast::Sender<HashMap<String, PokemonTeam>>,
+    tx: broadcast::Sender<TeamBroadcast>,
+    config: Config,
+    /// When the watcher last broadcast a change, in unix millis
+    last_updated: AtomicU64,
+    /// Number of connected WebSocket clients
+    clients: AtomicUsize,
+    /// Sequence number of the last broadcast
+    seq: AtomicU64,
+    write_limiter: RateLimiter,
+    sprite_aliases: SpriteAliases,
+    /// Asks the file watcher to re-read and broadcast every team
+    reload: Notify,
+    metrics: Metrics,
+    /// Set to `true` once the server is shutting down
+    shutdown: watch::Receiver<bool>,
+}
+
+impl AppState {
+    fn mark_updated(&self) -> u64 {
+        let now = unix_millis();
+        self.last_updated.store(now, Ordering::Relaxed);
+        now
+    }
+
+    fn last_updated(&self) -> u64 {
+        self.last_updated.load(Ordering::Relaxed)
+    }
+
+    /// `read_team_files`, recorded for `/metrics`.
+    fn read_teams(&self) -> Result<Teams, std::io::Error> {
+        let start = Instant::now();
+        let teams = read_team_files(&self.config, &self.sprite_aliases);
+        self.metrics.record_read(start.elapsed(), teams.is_ok());
+        teams
+    }
+
+    /// Send an update to every client. Updates are numbered so a client can
+    /// skip the ones its initial snapshot already includes.
+    fn broadcast(&self, update: ServerMsg, updated_at: u64) {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+        let update = TeamBroadcast::new(update, updated_at, seq, self.config.single_team);
+        let _ = self.tx.send(update);
+    }
+
+    /// Every team as sent to clients when they connect, along with the
+    /// sequence number of the last broadcast it includes.
+    fn snapshot(&self) -> Result<(u64, TeamBroadcast), std::io::Error> {
+        // Read the sequence first: files are read after it, so they're at
+        // least as new as any broadcast numbered up to it
+        let seq = self.seq.load(Ordering::SeqCst);
+        let teams = Arc::new(self.read_teams()?);
+        let update = TeamBroadcast::new(
+            ServerMsg::FullSync { teams },
+            self.last_updated(),
+            seq,
+            self.config.single_team,
+        );
+        Ok((seq, update))
+    }
+
+    /// Error sent to a client that couldn't be given the teams, on connect or
+    /// when resyncing after it lagged behind. It isn't part of the broadcast
+    /// sequence.
+    fn snapshot_error(&self, e: &std::io::Error) -> TeamBroadcast {
+        warn!("Failed to read team files for a client: {}", e);
+        let update = ServerMsg::Error {
+            message: format!("Couldn't read team files: {}", e),
+        };
+        TeamBroadcast::new(update, self.last_updated(), 0, self.config.single_team)
+    }
+
+    /// A single team read fresh from disk, for a client that asked for it.
+    /// Like `snapshot_error` it isn't part of the broadcast sequence.
+    fn team_snapshot(&self, name: &str) -> TeamBroadcast {
+        let update = match self.read_teams() {
+            Ok(mut teams) => match teams.swap_remove(name) {
+                Some(data) => ServerMsg::Delta {
+                    team: name.to_string(),
+                    data,
+                },
+                None => ServerMsg::Error {
+                    message: format!("No team named '{}'", name),
+                },
+            },
+            Err(e) => ServerMsg::Error {
+                message: format!("Couldn't read team files: {}", e),
+            },
+        };
+        TeamBroadcast::new(update, self.last_updated(), 0, self.config.single_team)
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
 }
 
 #[tokio::main]
 async fn main() {
+    let config = Config::parse();
+
+    // Flushes the log file when dropped, so keep it until main returns
+    let _log_guard = init_logging(&config);
+
+    let sprite_aliases = SpriteAliases::load();
+
+    if let Some(Command::Validate { file }) = &config.command {
+        std::process::exit(validate_team_file(&config, &sprite_aliases, file));
+    }
+
     // Create directories if they don't exist
+    fs::create_dir_all(&config.team_dir).expect("Failed to create team directory");
     fs::create_dir_all(SPRITES_DIR).expect("Failed to create sprites directory");
     fs::create_dir_all(STATIC_DIR).expect("Failed to create static directory");
 
     // Create team file if it doesn't exist
-    if !path::Path::new(TEAM_FILE).exists() {
-        let default_team = "pikachu\ncharizard\nblastoise\nvenusaur\nmewtwo\ndragonite\n";
-        fs::write(TEAM_FILE, default_team).expect("Failed to create team file");
+    let team_file = config.team_dir.join(TEAM_FILE);
+    let default_team: String = config
+        .default_team
+        .iter()
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| format!("{}\n", name))
+        .collect();
+    if !team_file.exists() {
+        fs::write(&team_file, &default_team).expect("Failed to create team file");
+    } else if fs::metadata(&team_file).is_ok_and(|metadata| metadata.len() == 0) {
+        // An emptied file shows a blank overlay, which looks broken
+        if config.repopulate_empty {
+            info!("{} is empty, writing the default team", team_file.display());
+            fs::write(&team_file, &default_team).expect("Failed to write team file");
+        } else {
+            warn!(
+                "{} is empty, so the overlay will show no Pokemon. Add one per line \
+                 or start with --repopulate-empty to restore the default team.",
+                team_file.display()
+            );
+        }
+    }
+
+    // List sprites to add before going live. Missing sprites are downloaded
+    // (and failures logged) instead when fetching is enabled.
+    if !config.fetch_sprites {
+        if let Ok(teams) = read_team_files(&config, &sprite_aliases) {
+            sprites::warn_missing_sprites(&config, &sprite_aliases, &teams);
+        }
     }
 
+    // Signals the file watcher and streaming clients to stop once the server
+    // shuts down
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
     // Create broadcast channel for team updates
-    let (tx, _) = broadcast::channel::<HashMap<String, PokemonTeam>>(100);
-    let state = Arc::new(AppState { tx: tx.clone() });
+    let (tx, _) = broadcast::channel::<TeamBroadcast>(config.channel_capacity as usize);
+    let state = Arc::new(AppState {
+        tx: tx.clone(),
+        config: config.clone(),
+        last_updated: AtomicU64::new(unix_millis()),
+        clients: AtomicUsize::new(0),
+        seq: AtomicU64::new(0),
+        write_limiter: RateLimiter::new(config.max_writes_per_sec),
+        sprite_aliases,
+        reload: Notify::new(),
+        metrics: Metrics::default(),
+        shutdown: shutdown_rx.clone(),
+    });
 
     // Setup file watcher with event-based monitoring
-    let tx_watcher = tx.clone();
-    tokio::spawn(async move {
-        if let Err(e) = watch_team_files(tx_watcher).await {
-            eprintln!("File watcher error: {}", e);
+    let state_watcher = state.clone();
+    let watcher_shutdown = shutdown_rx.clone();
+    let watcher_task = tokio::spawn(async move {
+        if let Err(e) = watch_team_files(state_watcher, watcher_shutdown).await {
+            error!("File watcher error: {}", e);
         }
     });
 
+    // Routes that write team files or push updates, guarded by the optional
+    // auth token
+    let mutating = Router::new()
+        .route("/teams/:name", post(update_team_handler))
+        .route("/reload", get(reload_handler).post(reload_handler))
+        // Team JSON is tiny, so anything bigger is a mistake or an attack
+        .route_layer(RequestBodyLimitLayer::new(config.max_body_size))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit_writes,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_token,
+        ));
+
+    let cors = if config.cors_origin.is_empty() {
+        CorsLayer::permissive()
+    } else {
+        CorsLayer::permissive().allow_origin(AllowOrigin::list(config.cors_origin.clone()))
+    };
+
+    // The overlay and editor pages, left out with --api-only
+    let frontend = Router::new()
+        .route(
+            "/",
+            get(|state, headers| async { embedded_static(state, Path("".into()), headers).await }),
+        )
+        .route(
+            "/edit",
+            get(|state, headers| async {
+                embedded_static(state, Path("edit.html".into()), headers).await
+            }),
+        )
+        // Requested by browsers on every page load
+        .route(
+            "/favicon.ico",
+            get(|state, headers| async {
+                embedded_static(state, Path("favicon.ico".into()), headers).await
+            }),
+        )
+        .route("/*path", get(embedded_static));
+
     // Build the router
     let app = Router::new()
+        .route("/healthz", get(|| async { "ok" }))
         .route("/ws", get(websocket_handler))
-        .nest_service("/sprites", ServeDir::new(SPRITES_DIR))
+        .route("/events", get(events_handler))
+        .route("/config", get(config_handler))
+        .route("/stats", get(stats_handler))
+        .route("/version", get(version_handler))
+        .route("/teams", get(teams_handler))
+        .route("/teams/_names", get(team_names_handler))
+        .route("/teams/:name/showdown", get(showdown_export_handler))
         .route(
-            "/",
-            get(|| async { embedded_static(Path("".into())).await }),
+            "/teams/:name/spritesheet.png",
+            get(spritesheet::spritesheet_handler),
         )
-        .route("/*path", get(embedded_static))
-        .layer(CorsLayer::permissive())
+        .route("/parse", post(parse_handler))
+        .merge(mutating)
+        .route("/sprites/_index", get(sprites::sprite_index_handler))
+        .route("/sprites/*file", get(sprites::sprite_handler))
+        .route(
+            "/team-sprites/:team/*file",
+            get(sprites::team_sprite_handler),
+        );
+    let app = if config.metrics {
+        app.route("/metrics", get(metrics::metrics_handler))
+    } else {
+        app
+    };
+    let app = if config.api_only {
+        app.fallback(api_not_found)
+    } else {
+        app.merge(frontend)
+    };
+    let app = app
+        // Defined paths called with the wrong method, which would otherwise
+        // get an empty body
+        .method_not_allowed_fallback(method_not_allowed)
+        // The default predicate skips images, so sprites aren't compressed
+        // twice. Videos are skipped too since they're served in ranges.
+        .layer(
+            CompressionLayer::new()
+                .compress_when(DefaultPredicate::new().and(NotForContentType::const_new("video/"))),
+        )
+        .layer(cors)
         .with_state(state);
 
-    // Start the server
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .expect("Failed to bind to port 3000");
+    // Start the server. Keep going as long as one address could be bound,
+    // e.g. when IPv6 is unavailable.
+    let mut listeners = Vec::new();
+    for addr in config.addrs() {
+        match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => warn!("Failed to bind to {}: {}", addr, e),
+        }
+    }
+    if listeners.is_empty() {
+        panic!("Failed to bind to any address");
+    }
+
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            // axum-server doesn't pick a crypto provider, and rustls can't
+            // choose one itself if dependencies ever enable more than one
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            Some(
+                RustlsConfig::from_pem_file(cert, key)
+                    .await
+                    .expect("Failed to load TLS certificate"),
+            )
+        }
+        _ => None,
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
 
-    println!("🚀 Server running on http://127.0.0.1:3000");
-    println!("📝 Edit '{}' to update your Pokemon team", TEAM_FILE);
-    println!("  - You can also create additional team files containing 'team' in their name.");
-    println!("  - Putting 'team' search param in the URL will switch teams.");
-    println!(
+    for listener in &listeners {
+        let addr = listener.local_addr().expect("Listener has a local address");
+        for addr in reachable_addrs(addr) {
+            info!("🚀 Server running on {}://{}", scheme, addr);
+        }
+    }
+    info!(
+        "📝 Edit '{}' to update your Pokemon team",
+        team_file.display()
+    );
+    info!("  - You can also create additional team files containing 'team' in their name.");
+    info!(
+        "  - Putting '{}' search param in the URL will switch teams.",
+        config.team_param
+    );
+    info!(
         "🖼️  Place your Pokemon sprites in the '{}' directory",
         SPRITES_DIR
     );
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+    notify_ready();
+
+    if config.open {
+        let mut addr = listeners[0]
+            .local_addr()
+            .expect("Listener has a local address");
+        // Browsers can't connect to 0.0.0.0 or ::, use loopback instead
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let url = format!("{}://{}", scheme, addr);
+        if let Err(e) = open::that_detached(&url) {
+            warn!("Failed to open {} in the browser: {}", url, e);
+        }
+    }
+
+    tokio::spawn(shutdown_signal(shutdown_tx));
+
+    let servers = listeners.into_iter().map(|listener| {
+        let mut shutdown_rx = shutdown_rx.clone();
+        let shutdown = async move {
+            let _ = shutdown_rx.wait_for(|&shutdown| shutdown).await;
+        };
+        match &tls {
+            Some(tls) => serve_tls(listener, app.clone(), tls.clone(), shutdown).boxed(),
+            None => axum::serve(listener, app.clone())
+                .with_graceful_shutdown(shutdown)
+                .into_future()
+                .boxed(),
+        }
+    });
+    for result in futures::future::join_all(servers).await {
+        result.expect("Failed to start server");
+    }
+
+    let _ = watcher_task.await;
 }
 
-async fn embedded_static(Path(path): Path<String>) -> Response<Body> {
-    let path = if path.is_empty() {
-        "index.html"
+/// Tell systemd the server is up when it runs as a `Type=notify` service.
+/// Does nothing when NOTIFY_SOCKET isn't set.
+#[cfg(target_os = "linux")]
+fn notify_ready() {
+    if let Err(e) = sd_notify::notify(&[sd_notify::NotifyState::Ready]) {
+        warn!("Failed to notify systemd that the server is ready: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn notify_ready() {}
+
+/// Log to the console, and to `--log-file` if set. The file gets every
+/// message even with `--quiet`, since it is meant for bug reports.
+fn init_logging(config: &Config) -> Option<WorkerGuard> {
+    // Default to info so the startup banner shows, RUST_LOG overrides it
+    let default_filter =
+        || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let console_filter = if config.quiet {
+        EnvFilter::new("warn")
     } else {
-        path.as_str()
+        default_filter()
     };
+    let console = tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .with_filter(console_filter);
 
-    match Assets::get(path) {
-        Some(file) => Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, utils::content_type(path))
-            .header(header::CACHE_CONTROL, "no-store")
-            .body(Body::from(file.data))
-            .unwrap(),
-        None => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::from("404"))
-            .unwrap(),
+    let mut file_error = None;
+    let (file, guard) = match config.log_file.as_deref().map(log_file_appender) {
+        Some(Ok(appender)) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .with_ansi(false)
+                .with_writer(writer)
+                .with_filter(default_filter());
+            (Some(layer), Some(guard))
+        }
+        Some(Err(e)) => {
+            file_error = Some(e);
+            (None, None)
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .init();
+
+    if let Some(e) = file_error {
+        warn!("Not logging to a file: {}", e);
     }
+    guard
 }
 
-async fn websocket_handler(
-    ws: WebSocketUpgrade,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+/// Daily rotated appender for `--log-file`: `overlay.log` is written to
+/// `overlay.2024-05-01.log` and so on, keeping the last week.
+fn log_file_appender(path: &path::Path) -> Result<rolling::RollingFileAppender, String> {
+    const MAX_LOG_FILES: usize = 7;
+
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(path::Path::new("."));
+    let prefix = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| format!("invalid log file name '{}'", path.display()))?;
+
+    let mut builder = rolling::RollingFileAppender::builder()
+        .rotation(rolling::Rotation::DAILY)
+        .filename_prefix(prefix)
+        .max_log_files(MAX_LOG_FILES);
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        builder = builder.filename_suffix(extension);
+    }
+    builder.build(dir).map_err(|e| e.to_string())
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
-    let (mut sender, _receiver) = socket.split();
-    let mut rx = state.tx.subscribe();
+/// Serve HTTPS (and `wss` WebSockets) on an already bound listener.
+async fn serve_tls(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    tls: RustlsConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let handle = axum_server::Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        shutdown_handle.graceful_shutdown(None);
+    });
 
-    // Send initial team state
-    if let Ok(team) = read_team_files() {
-        let json = serde_json::to_string(&team).unwrap();
-        if sender.send(Message::Text(json)).await.is_err() {
-            return;
+    axum_server::from_tcp_rustls(listener.into_std()?, tls)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await
+}
+
+/// Print a team file the way the overlay will show it. Returns the exit code:
+/// non zero if the file can't be read or a Pokemon has no sprite.
+fn validate_team_file(config: &Config, aliases: &SpriteAliases, file: &path::Path) -> i32 {
+    let team = match read_team_file(file, config, aliases) {
+        Ok(team) => team,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", file.display(), e);
+            return 1;
         }
-    }
+    };
+    let file_name = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let key = team_key(file_name);
 
-    // Listen for team updates and forward to websocket
-    while let Ok(team) = rx.recv().await {
-        let json = serde_json::to_string(&team).unwrap();
-        if sender.send(Message::Text(json)).await.is_err() {
-            break;
+    let mut missing = 0;
+    for (slot, pokemon) in team.pokemon.iter().enumerate() {
+        let slot = slot + 1;
+        if pokemon.empty {
+            println!("{}. (empty)", slot);
+            continue;
+        }
+
+        let name = match &pokemon.nickname {
+            Some(nickname) => format!("{} ({})", pokemon.name, nickname),
+            None => pokemon.name.clone(),
+        };
+        if pokemon.sprite_data.is_some() {
+            println!("{}. {}  (inline sprite)", slot, name);
+        } else if sprites::find_team_sprite(config, aliases, &key, &pokemon.name).is_some() {
+            println!("{}. {}", slot, name);
+        } else {
+            println!(
+                "{}. {}  <- no sprite, expected '{}.png'",
+                slot,
+                name,
+                utils::slug(aliases.resolve(&pokemon.name))
+            );
+            missing += 1;
         }
     }
+
+    if missing > 0 {
+        eprintln!("{} Pokemon without a sprite", missing);
+        return 1;
+    }
+    0
 }
 
-async fn watch_team_files(
-    tx: broadcast::Sender<HashMap<String, PokemonTeam>>,
-) -> notify::Result<()> {
-    use notify::{Config, EventKind};
+/// Addresses clients can use to reach a listener. Unspecified addresses
+/// (0.0.0.0 or ::) are expanded to the addresses of every interface.
+fn reachable_addrs(addr: SocketAddr) -> Vec<SocketAddr> {
+    if !addr.ip().is_unspecified() {
+        return vec![addr];
+    }
 
-    let (notify_tx, mut notify_rx) = tokio::sync::mpsc::channel(100);
+    let interfaces = if_addrs::get_if_addrs().unwrap_or_default();
+    let addrs: Vec<SocketAddr> = interfaces
+        .iter()
+        .map(|interface| interface.ip())
+        // A dual-stack IPv6 socket also accepts IPv4 connections
+        .filter(|ip| ip.is_ipv4() || addr.is_ipv6())
+        .map(|ip| SocketAddr::new(ip, addr.port()))
+        .collect();
 
-    // Create watcher with custom config
-    let config = Config::default().with_poll_interval(std::time::Duration::from_secs(1));
+    if addrs.is_empty() {
+        vec![addr]
+    } else {
+        addrs
+    }
+}
 
-    let mut watcher = notify::RecommendedWatcher::new(
-        move |res: Result<Event, notify::Error>| {
-            if let Ok(event) = res {
-                let _ = notify_tx.blocking_send(event);
-            }
-        },
-        config,
-    )?;
+async fn shutdown_signal(shutdown_tx: watch::Sender<bool>) {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for Ctrl-C");
 
-    for file in get_team_files() {
-        let file_path = path::Path::new(&file);
-        watcher.watch(file_path, RecursiveMode::NonRecursive)?;
+    info!("🛑 Shutting down...");
+    let _ = shutdown_tx.send(true);
+}
+
+async fn embedded_static(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let mut path = if path.is_empty() {
+        "index.html"
+    } else {
+        path.as_str()
+    };
+
+    // Don't rely on rust_embed to reject traversal attempts
+    if !utils::is_safe_path(path) {
+        return not_found_page();
     }
 
-    // Send initial state
-    if let Ok(team) = read_team_files() {
-        let _ = tx.send(team);
+    let mut data = load_static(&state.config, path).await;
+
+    // Paths without an extension aren't assets, let the overlay route them
+    let is_asset = path
+        .rsplit('/')
+        .next()
+        .is_some_and(|name| name.contains('.'));
+    if data.is_none() && !is_asset {
+        path = "index.html";
+        data = load_static(&state.config, path).await;
     }
 
-    // Watch for file changes
-    loop {
-        match notify_rx.recv().await {
-            Some(event) => {
-                // Check if the event is related to our file
-                let is_team_file = event.paths.iter().any(|p| {
-                    p.file_name()
-                        .map_or(false, |name| name.to_string_lossy().contains("team"))
-                });
+    let Some(data) = data else {
+        return not_found_page();
+    };
+    let data = match data {
+        Cow::Borrowed(data) => Bytes::from_static(data),
+        Cow::Owned(data) => Bytes::from(data),
+    };
 
-                if !is_team_file {
-                    println!("Ignoring event for non-team file: {:?}", event.paths);
-                    continue;
-                }
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, utils::content_type(path))
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(header::ACCEPT_RANGES, "bytes");
 
-                match event.kind {
-                    EventKind::Remove(_) => {
-                        // Keep watching the file if it is recreated
-                        for path in event.paths {
-                            if let Some(file_name) = path.file_name() {
-                                if file_name.to_string_lossy().contains("team") {
-                                    let _ = watcher.watch(&path, RecursiveMode::NonRecursive);
-                                }
-                            }
-                        }
-                    }
-                    EventKind::Modify(_) | EventKind::Create(_) | EventKind::Any => {
-                        // Small delay to ensure file write is complete
-                        tokio::time::sleep(tokio::time::Duration::from_millis(150)).await;
+    // Lets browsers seek in large assets like background videos without
+    // downloading all of them
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    match utils::byte_range(range, data.len()) {
+        utils::ByteRange::Full => builder
+            .status(StatusCode::OK)
+            .body(Body::from(data))
+            .unwrap(),
+        utils::ByteRange::Partial(range) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, data.len()),
+            )
+            .body(Body::from(data.slice(range)))
+            .unwrap(),
+        utils::ByteRange::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", data.len()))
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
 
-                        // Check if content actually changed
-                        if let Ok(team) = read_team_files() {
-                            let _ = tx.send(team);
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            None => {
-                eprintln!("File watcher channel closed");
-                break;
-            }
+async fn load_static(config: &Config, path: &str) -> Option<Cow<'static, [u8]>> {
+    if config.dev {
+        // Fall back to the embedded copy for files that aren't on disk
+        if let Ok(data) = tokio::fs::read(path::Path::new(STATIC_DIR).join(path)).await {
+            return Some(Cow::Owned(data));
         }
     }
+    Assets::get(path).map(|file| file.data)
+}
 
-    // Keep watcher alive
-    drop(watcher);
-    Ok(())
+fn not_found_page() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(
+            "<!doctype html><title>404 Not Found</title><h1>404 Not Found</h1>",
+        ))
+        .unwrap()
 }
 
-fn get_team_files() -> Vec<String> {
-    fs::read_dir(path::Path::new("."))
-        .expect("Failed to read current directory")
-        .collect::<Vec<Result<DirEntry, std::io::Error>>>()
-        .into_iter()
-        .map(|res| res.unwrap())
-        .collect::<Vec<DirEntry>>()
-        .into_iter()
-        .filter(|entry| {
-            let entry_string = entry
-                .file_name()
-                .into_string()
-                .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Invalid filename"))
-                .unwrap();
-            entry.path().is_file()
-                && entry_string.contains("team")
-                && entry_string.ends_with(".txt")
-        })
-        .map(|res| res.file_name().into_string().unwrap())
-        .collect()
+async fn config_handler(State(state): State<Arc<AppState>>) -> Json<ClientConfig> {
+    Json(state.config.client_config())
 }
 
-fn read_team_files() -> Result<HashMap<String, PokemonTeam>, std::io::Error> {
-    let files = get_team_files();
+async fn version_handler() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "version": env!("CARGO_PKG_VERSION") }))
+}
 
-    println!("Reading team files: {:?}", files);
+async fn stats_handler(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let clients = state.clients.load(Ordering::Relaxed);
+    Json(serde_json::json!({ "clients": clients }))
+}
 
-    let mut teams = HashMap::new();
+async fn teams_handler(State(state): State<Arc<AppState>>) -> Response<Body> {
+    match state.read_teams() {
+        Ok(teams) if state.config.single_team && teams.len() == 1 => {
+            Json(teams.into_values().next()).into_response()
+        }
+        Ok(teams) => Json(teams).into_response(),
+        Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
 
-    for file in files {
-        let content = fs::read_to_string(&file)?;
-        let pokemon: Vec<Pokemon> = content
-            .lines()
-            .map(|line| {
-                let parts: Vec<&str> = line.trim().split(":").collect();
-                let name = parts[0].to_string();
-                let nickname = if parts.len() > 1 {
-                    Some(parts[1..].join(" "))
-                } else {
-                    None
-                };
-                Pokemon { name, nickname }
-            })
-            .filter(|pokemon| !pokemon.name.is_empty())
-            .take(6) // Only take first 6 Pokemon
-            .collect();
+/// Team keys, sorted, so overlays can offer a team switcher.
+async fn team_names_handler(State(state): State<Arc<AppState>>) -> Response<Body> {
+    match get_team_files(&state.config) {
+        Ok(files) => {
+            let mut names: Vec<String> = files.iter().map(|file| team_key(file)).collect();
+            names.sort_unstable();
+            names.dedup();
+            Json(names).into_response()
+        }
+        Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
 
-        // Pad with empty strings if less than 6
-        let mut pokemon_team = pokemon;
-        while pokemon_team.len() < 6 {
-            pokemon_team.push(Pokemon {
-                name: String::new(),
-                nickname: None,
-            });
+/// A team as a Showdown export, to paste into the teambuilder.
+async fn showdown_export_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response<Body> {
+    let teams = match state.read_teams() {
+        Ok(teams) => teams,
+        Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let Some(team) = teams.get(&name) else {
+        return json_error(StatusCode::NOT_FOUND, "Team not found");
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(showdown::format(&team.pokemon)))
+        .unwrap()
+}
+
+#[derive(Debug, Deserialize)]
+struct ParseParams {
+    /// Extension of the file the body would be in, `txt` by default
+    format: Option<String>,
+    /// Team the sprite URLs are resolved for (see --sprites-map)
+    #[serde(default)]
+    team: String,
+}
+
+/// Parse a team file sent as the body, without writing it, to see how the
+/// server would read it.
+async fn parse_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ParseParams>,
+    body: String,
+) -> Response<Body> {
+    let format = params.format.as_deref().unwrap_or("txt");
+    if !TEAM_FILE_EXTENSIONS.contains(&format) {
+        return json_error(StatusCode::BAD_REQUEST, "Unknown team file format");
+    }
+
+    match parse_team_file(
+        &body,
+        Some(format),
+        "POST /parse",
+        &state.config,
+        &state.sprite_aliases,
+    ) {
+        Ok(mut team) => {
+            set_sprite_urls(
+                &state.config,
+                &state.sprite_aliases,
+                &params.team,
+                &mut team,
+            );
+            Json(team).into_response()
         }
+        Err(e) => json_error(StatusCode::BAD_REQUEST, &e.to_string()),
+    }
+}
 
-        teams.insert(
-            file.split('.').next().unwrap().to_string(),
-            PokemonTeam {
-                pokemon: pokemon_team,
-            },
-        );
+async fn update_team_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(team): Json<PokemonTeam>,
+) -> Response<Body> {
+    // Only allow plain file names so writes stay inside the team directory
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return json_error(StatusCode::BAD_REQUEST, "Invalid team name");
+    }
+    // Names starting with `_` are reserved for routes like /teams/_names
+    if name.starts_with('_') {
+        return json_error(StatusCode::BAD_REQUEST, "Team names can't start with '_'");
     }
+    let config = &state.config;
 
-    Ok(teams)
+    // Save over the file the team was read from, in its format, so saving
+    // doesn't leave a second file with the same key
+    let files = match get_team_files(config) {
+        Ok(files) => files,
+        Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let mut existing = files.iter().filter(|file| team_key(file) == name);
+    let (file, formats) = match (existing.next(), existing.next()) {
+        (Some(_), Some(_)) => {
+            return json_error(
+                StatusCode::CONFLICT,
+                "Several files have this team name, edit them directly",
+            );
+        }
+        (Some(file), None) => {
+            let content = match read_team_content(&config.team_dir.join(file)) {
+                Ok(content) => content,
+                Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            };
+            let extension = path::Path::new(file)
+                .extension()
+                .and_then(|ext| ext.to_str());
+            let content = content.strip_prefix('\u{feff}').unwrap_or(&content);
+            let formats = match TeamFormat::detect(extension, content) {
+                // A Showdown export of bare names looks like the line format,
+                // which then has to hold it
+                TeamFormat::Showdown => vec![TeamFormat::Showdown, TeamFormat::Lines],
+                format => vec![format],
+            };
+            (file.clone(), formats)
+        }
+        (None, _) => {
+            let file = format!("{}.txt", name);
+            if !is_team_file_name(config, &file) {
+                return json_error(
+                    StatusCode::BAD_REQUEST,
+                    "Team name must contain 'team' or be listed in --team-files",
+                );
+            }
+            (file, vec![TeamFormat::Lines])
+        }
+    };
+
+    let Some(content) = format_team_for_save(
+        &team.pokemon,
+        &file,
+        &formats,
+        config,
+        &state.sprite_aliases,
+    ) else {
+        return json_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &format!(
+                "{} can't hold every field of this team, edit it directly",
+                file
+            ),
+        );
+    };
+
+    // The file watcher broadcasts the change once the write lands
+    match fs::write(config.team_dir.join(&file), content) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+/// Content to save a team as in `file`, in the first of `formats` that reads
+/// back as the same team. `None` if saving would lose fields, e.g. moves in
+/// the line format.
+fn format_team_for_save(
+    pokemon: &[Pokemon],
+    file: &str,
+    formats: &[TeamFormat],
+    config: &Config,
+    aliases: &SpriteAliases,
+) -> Option<String> {
+    // Same clean up and cap as when reading the file
+    let mut pokemon = pokemon.to_vec();
+    for pokemon in &mut pokemon {
+        normalize_pokemon(pokemon, file, aliases);
+    }
+    pokemon.retain(|pokemon| !pokemon.name.is_empty());
+    pokemon.truncate(config.team_size.into());
+
+    // Only what is stored in the file has to survive. Text formats have no
+    // types, they are always looked up.
+    let stored = |pokemon: &Pokemon, format: TeamFormat| Pokemon {
+        types: match format {
+            TeamFormat::Lines | TeamFormat::Showdown => Vec::new(),
+            TeamFormat::Toml | TeamFormat::Yaml => pokemon.types.clone(),
+        },
+        suspicious_name: false,
+        sprite_url: None,
+        empty: false,
+        ..pokemon.clone()
+    };
+    let extension = path::Path::new(file)
+        .extension()
+        .and_then(|ext| ext.to_str());
+    formats.iter().find_map(|&format| {
+        let content = format_team_as(&pokemon, format, aliases).ok()?;
+        let reread = parse_team_file(&content, extension, file, config, aliases).ok()?;
+        let reread = reread.pokemon.iter().filter(|pokemon| !pokemon.empty);
+        reread
+            .map(|pokemon| stored(pokemon, format))
+            .eq(pokemon.iter().map(|pokemon| stored(pokemon, format)))
+            .then_some(content)
+    })
+}
+
+/// Re-read every team file and broadcast the result, for when the file
+/// watcher missed a change (network drives, unusual editors).
+async fn reload_handler(State(state): State<Arc<AppState>>) -> StatusCode {
+    // The watcher reads and sends the teams, so it keeps track of them
+    state.reload.notify_one();
+    StatusCode::ACCEPTED
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response<Body> {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+async fn api_not_found() -> Response<Body> {
+    json_error(StatusCode::NOT_FOUND, "Not found")
+}
+
+async fn method_not_allowed() -> Response<Body> {
+    json_error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+}
+
+async fn websocket_handler(
+    ws: Option<WebSocketUpgrade>,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WsParams>,
+) -> Response<Body> {
+    // Opening /ws in a browser tab is a plain GET
+    let Some(ws) = ws else {
+        let mut response = json_error(
+            StatusCode::UPGRADE_REQUIRED,
+            "This endpoint only accepts WebSocket connections",
+        );
+        response.headers_mut().insert(
+            header::UPGRADE,
+            header::HeaderValue::from_static("websocket"),
+        );
+        return response;
+    };
+    ws.on_upgrade(move |socket| handle_socket(socket, state, params.enc))
+}
+
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>, encoding: Encoding) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut rx = state.tx.subscribe();
+
+    // Counted until the handler returns, whichever way it does
+    struct ClientGuard<'a>(&'a AtomicUsize);
+    impl Drop for ClientGuard<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+            debug!("WebSocket client disconnected");
+        }
+    }
+    state.clients.fetch_add(1, Ordering::Relaxed);
+    let _client = ClientGuard(&state.clients);
+    debug!("WebSocket client connected");
+
+    // Team picked by a subscribe message, all teams are sent until then
+    let mut subscription: Option<String> = None;
+
+    // Ping periodically so idle connections aren't reaped by proxies, and
+    // drop clients that stop answering
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut last_pong = Instant::now();
+
+    // Send initial team state. Broadcasts already queued for this client
+    // are included in it, so they are skipped below. Without it the client
+    // is told why, and stays connected for the next change.
+    let mut seen = 0;
+    let initial = match state.snapshot() {
+        Ok((seq, snapshot)) => {
+            seen = seq;
+            snapshot
+        }
+        Err(e) => state.snapshot_error(&e),
+    };
+    let message = initial
+        .message_for(None, encoding)
+        .expect("Unfiltered snapshot is always sent");
+    if sender.send(message).await.is_err() {
+        return;
+    }
+
+    // Listen for team updates and forward to websocket
+    loop {
+        let update = tokio::select! {
+            update = rx.recv() => update,
+            msg = receiver.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str(&text) {
+                        Ok(ClientMsg::Subscribe(team)) => subscription = Some(team),
+                        Ok(ClientMsg::Get(team)) => {
+                            let message = state
+                                .team_snapshot(&team)
+                                .message_for(None, encoding)
+                                .expect("Unfiltered update is always sent");
+                            if sender.send(message).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => {}
+                    }
+                    continue;
+                }
+                Some(Ok(Message::Pong(_))) => {
+                    last_pong = Instant::now();
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            _ = ping.tick() => {
+                if last_pong.elapsed() > PING_INTERVAL + PONG_TIMEOUT {
+                    break;
+                }
+                if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let update = match update {
+            Ok(update) if update.seq <= seen => continue,
+            Ok(update) => update,
+            Err(RecvError::Lagged(skipped)) => {
+                // Missed some updates, resync the client with a full snapshot
+                debug!("WebSocket client missed {} updates, resyncing", skipped);
+                match state.snapshot() {
+                    Ok((seq, snapshot)) => {
+                        seen = seq;
+                        snapshot
+                    }
+                    Err(e) => state.snapshot_error(&e),
+                }
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Some(message) = update.message_for(subscription.as_deref(), encoding) else {
+            continue;
+        };
+        if sender.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Server-Sent Events alternative to the WebSocket for browser sources that
+/// block WebSockets. Sends the same payloads.
+async fn events_handler(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let rx = state.tx.subscribe();
+    // Graceful shutdown waits for open responses, and the broadcast channel
+    // never closes on its own
+    let mut shutdown = state.shutdown.clone();
+    let stopped = async move {
+        let _ = shutdown.wait_for(|&shutdown| shutdown).await;
+    };
+    let (seen, initial) = match state.snapshot() {
+        Ok((seq, snapshot)) => (seq, snapshot.json),
+        Err(e) => (0, state.snapshot_error(&e).json),
+    };
+    // Moved forward by resyncs, so updates they include are skipped too
+    let seen = Arc::new(AtomicU64::new(seen));
+    let updates = BroadcastStream::new(rx).filter_map(move |update| {
+        let state = state.clone();
+        let seen = seen.clone();
+        async move {
+            match update {
+                // Already part of the initial snapshot
+                Ok(update) if update.seq <= seen.load(Ordering::Relaxed) => None,
+                Ok(update) => Some(update.json),
+                // Missed some updates, resync the client with a full snapshot
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    debug!("SSE client missed {} updates, resyncing", skipped);
+                    match state.snapshot() {
+                        Ok((seq, snapshot)) => {
+                            seen.store(seq, Ordering::Relaxed);
+                            Some(snapshot.json)
+                        }
+                        Err(e) => Some(state.snapshot_error(&e).json),
+                    }
+                }
+            }
+        }
+    });
+
+    let events = futures::stream::once(async { initial })
+        .chain(updates)
+        .take_until(stopped)
+        .map(|json| Ok(sse::Event::default().data(&*json)));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn watch_team_files(
+    state: Arc<AppState>,
+    mut shutdown: watch::Receiver<bool>,
+) -> notify::Result<()> {
+    let config = &state.config;
+    let send = |update: ServerMsg| state.broadcast(update, state.mark_updated());
+
+    let (notify_tx, mut notify_rx) = tokio::sync::mpsc::channel(100);
+
+    // Create watcher with custom config
+    let watcher_config =
+        notify::Config::default().with_poll_interval(std::time::Duration::from_secs(1));
+
+    let mut watcher = notify::RecommendedWatcher::new(
+        move |res: Result<Event, notify::Error>| {
+            if let Ok(event) = res {
+                let _ = notify_tx.blocking_send(event);
+            }
+        },
+        watcher_config,
+    )?;
+
+    // Watch the directory rather than individual files so team files created
+    // (or deleted and recreated) after startup are picked up too
+    let recursive_mode = if config.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watch_with_retry(&mut watcher, &config.team_dir, recursive_mode).await?;
+    // Events carry absolute paths, so compare paths against these
+    let team_dir = std::path::absolute(&config.team_dir)?;
+    let sprites_dir = std::path::absolute(SPRITES_DIR)?;
+    if config.watch_sprites {
+        // Team updates still work without it, so don't give up on those
+        if let Err(e) =
+            watch_with_retry(&mut watcher, &sprites_dir, RecursiveMode::NonRecursive).await
+        {
+            warn!(
+                "Not watching {} for sprite changes: {}",
+                sprites_dir.display(),
+                e
+            );
+        }
+    }
+
+    // Last state sent to clients, so saves that don't change anything
+    // (e.g. editor autosave) don't trigger a re-render. Shared with the full
+    // sync broadcasts rather than copied.
+    let mut last_sent = Arc::new(Teams::new());
+    // Set while `last_sent` doesn't hold every team, so the next change is
+    // sent with a full read instead of as a delta
+    let mut stale = true;
+
+    // Send initial state
+    if let Ok(teams) = state.read_teams() {
+        fetch_missing_sprites(&state, teams.values());
+        last_sent = Arc::new(teams);
+        stale = false;
+        send(ServerMsg::FullSync {
+            teams: last_sent.clone(),
+        });
+    }
+
+    let debounce = Duration::from_millis(config.debounce_ms);
+    let max_burst = debounce * MAX_BURST_WINDOWS;
+
+    // Watch for file changes
+    loop {
+        let event = tokio::select! {
+            event = notify_rx.recv() => event,
+            _ = state.reload.notified() => {
+                // Sent even if nothing changed, clients may have missed it
+                match state.read_teams() {
+                    Ok(teams) => {
+                        info!("Reloading {} teams", teams.len());
+                        fetch_missing_sprites(&state, teams.values());
+                        last_sent = Arc::new(teams);
+                        stale = false;
+                        send(ServerMsg::FullSync {
+                            teams: last_sent.clone(),
+                        });
+                    }
+                    Err(e) => warn!("Failed to reload team files: {}", e),
+                }
+                continue;
+            }
+            _ = shutdown.changed() => break,
+        };
+        let Some(event) = event else {
+            error!("File watcher channel closed");
+            break;
+        };
+
+        // A single save usually produces several events, so collect them
+        // until none arrives within the debounce window. Only events for
+        // files the overlay uses extend it, and only up to a limit, so a
+        // file written to non-stop (like a log) can't hold back updates.
+        let burst_start = Instant::now();
+        let mut burst_end = burst_start + debounce;
+        let mut changes = BurstChanges::default();
+        let mut next = Some(event);
+        while let Some(event) = next.take() {
+            if changes.add(event, config, &team_dir, &sprites_dir) {
+                burst_end = (Instant::now() + debounce).min(burst_start + max_burst);
+            }
+            if let Ok(Some(event)) = timeout_at(burst_end, notify_rx.recv()).await {
+                next = Some(event);
+            }
+        }
+
+        let BurstChanges {
+            files: changed,
+            sprites: changed_sprites,
+            team_dir_moved,
+            ignore_changed,
+        } = changes;
+
+        if team_dir_moved {
+            // The old watch went with the directory, so watch whatever is at
+            // its path now
+            warn!(
+                "{} was moved or removed, watching it again",
+                config.team_dir.display()
+            );
+            let _ = watcher.unwatch(&config.team_dir);
+            if let Err(e) = watch_with_retry(&mut watcher, &config.team_dir, recursive_mode).await {
+                error!("Failed to watch {}: {}", config.team_dir.display(), e);
+            }
+        }
+
+        // Nobody would receive an update, so don't read the files. Forget
+        // what was sent so the next change is sent as a full sync once a
+        // client has connected.
+        if state.tx.receiver_count() == 0 {
+            trace!(
+                "No clients connected, skipping {} changed files",
+                changed.len()
+            );
+            if !changed.is_empty() || team_dir_moved || ignore_changed {
+                state.mark_updated();
+                last_sent = Arc::new(Teams::new());
+                stale = true;
+            }
+            continue;
+        }
+
+        // Only bother clients about sprites that are currently shown
+        for sprite in changed_sprites {
+            let shown = last_sent
+                .values()
+                .flat_map(|team| &team.pokemon)
+                .find(|pokemon| utils::slug(state.sprite_aliases.resolve(&pokemon.name)) == sprite);
+            if let Some(pokemon) = shown {
+                debug!("Sprite '{}' changed", sprite);
+                // Send the name as it appears in the team so clients can
+                // find the image
+                let update = ServerMsg::ReloadSprite {
+                    name: pokemon.name.clone(),
+                };
+                state.broadcast(update, state.last_updated());
+            }
+        }
+
+        if changed.is_empty() && !team_dir_moved && !ignore_changed {
+            continue;
+        }
+
+        // Editors doing an atomic save delete and recreate the file, so it
+        // only counts as removed if it is still missing after the burst. A
+        // moved directory may hold entirely different teams, and a changed
+        // .overlayignore may hide or reveal any of them. Several files
+        // changed in the same burst (e.g. a script rewriting every run) are
+        // sent together rather than as a delta each. So is a change after
+        // what was sent was forgotten, since a delta (or a --single-team full
+        // sync) needs every team to be known.
+        if stale
+            || team_dir_moved
+            || ignore_changed
+            || changed.len() > 1
+            || changed.iter().any(|path| !path.exists())
+        {
+            // A full sync also makes overlays drop removed teams
+            if let Ok(teams) = read_teams_settled(&state, &team_dir, &changed).await {
+                send_teams(&state, &mut last_sent, teams);
+                stale = false;
+            }
+            continue;
+        }
+
+        // Only re-read and broadcast the file that changed
+        let ignore = team_ignore(config);
+        for path in &changed {
+            let Some(file) = relative_team_file(&team_dir, path) else {
+                continue;
+            };
+            if is_ignored(&ignore, &file) {
+                continue;
+            }
+            let Ok(mut data) = read_settled_team_file(path, config, &state.sprite_aliases).await
+            else {
+                state.metrics.record_read_error();
+                continue;
+            };
+            let team = team_key(&file);
+            set_sprite_urls(config, &state.sprite_aliases, &team, &mut data);
+            send_team(&state, &mut last_sent, team, data);
+        }
+    }
+
+    // Keep watcher alive until the loop exits
+    drop(watcher);
+    Ok(())
+}
+
+/// What a burst of file events changed, see `watch_team_files`.
+#[derive(Debug, Default)]
+struct BurstChanges {
+    /// Team files, as absolute paths
+    files: HashSet<path::PathBuf>,
+    /// Slugs of sprites changed in the sprites directory (`--watch-sprites`)
+    sprites: HashSet<String>,
+    /// Set when the team directory itself was renamed or removed
+    team_dir_moved: bool,
+    /// Set when .overlayignore changed, which may add or drop any team
+    ignore_changed: bool,
+}
+
+impl BurstChanges {
+    /// Add what an event changed. Returns whether it touched anything the
+    /// overlay uses. `team_dir` and `sprites_dir` are absolute, like the
+    /// paths events carry.
+    fn add(
+        &mut self,
+        event: Event,
+        config: &Config,
+        team_dir: &path::Path,
+        sprites_dir: &path::Path,
+    ) -> bool {
+        use notify::{event::ModifyKind, EventKind};
+
+        let mut relevant = false;
+        let paths = match event.kind {
+            // Editors that save by writing `team.txt.tmp` and renaming it
+            // over `team.txt` only produce a rename. The temp file is ignored
+            // like other editor files, and the rename target is re-read like
+            // any other change. The watch is on the directory, so it survives
+            // the file being replaced, but not the directory itself being
+            // renamed.
+            EventKind::Modify(ModifyKind::Name(_)) | EventKind::Remove(_) => {
+                if event.paths.iter().any(|path| path == team_dir) {
+                    self.team_dir_moved = true;
+                    relevant = true;
+                }
+                event.paths
+            }
+            EventKind::Modify(_) | EventKind::Create(_) | EventKind::Any => event.paths,
+            // Accesses (like closing the file after a write) change nothing
+            _ => Vec::new(),
+        };
+
+        for path in paths {
+            if config.watch_sprites && path.parent() == Some(sprites_dir) {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    self.sprites.insert(utils::slug(stem));
+                    relevant = true;
+                }
+                continue;
+            }
+
+            if path == team_dir.join(IGNORE_FILE) {
+                self.ignore_changed = true;
+                relevant = true;
+                continue;
+            }
+
+            // Check if the event is related to our file
+            let is_team_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| is_team_file_name(config, name))
+                && relative_team_file(team_dir, &path).is_some();
+            if is_team_file {
+                self.files.insert(path);
+                relevant = true;
+            } else {
+                trace!("Ignoring event for non-team file: {:?}", path);
+            }
+        }
+        relevant
+    }
+}
+
+/// Broadcast every team as a full sync, unless it is exactly what was last
+/// sent.
+fn send_teams(state: &AppState, last_sent: &mut Arc<Teams>, teams: Teams) {
+    if teams == **last_sent {
+        return;
+    }
+    fetch_missing_sprites(state, teams.values());
+    *last_sent = Arc::new(teams);
+    let update = ServerMsg::FullSync {
+        teams: last_sent.clone(),
+    };
+    state.broadcast(update, state.mark_updated());
+}
+
+/// Broadcast a team re-read after its file changed, unless its Pokemon are
+/// the ones last sent. `last_sent` has to hold every team.
+fn send_team(state: &AppState, last_sent: &mut Arc<Teams>, team: String, data: PokemonTeam) {
+    // Saves that only touch the modification time aren't worth a re-render
+    // either
+    if last_sent.get(&team).map(|sent| &sent.pokemon) == Some(&data.pokemon) {
+        return;
+    }
+
+    fetch_missing_sprites(state, [&data]);
+    Arc::make_mut(last_sent).insert(team.clone(), data.clone());
+    // With a single team a delta is the whole state, so send it the way full
+    // syncs are sent in --single-team mode
+    let update = if state.config.single_team && last_sent.len() == 1 {
+        ServerMsg::FullSync {
+            teams: last_sent.clone(),
+        }
+    } else {
+        ServerMsg::Delta { team, data }
+    };
+    state.broadcast(update, state.mark_updated());
+}
+
+/// Every team for a full sync, with the files changed in the burst read
+/// until they settle (see `read_settled_team_file`), so a script rewriting
+/// several files can't get half-written teams sent.
+async fn read_teams_settled(
+    state: &AppState,
+    team_dir: &path::Path,
+    changed: &HashSet<path::PathBuf>,
+) -> Result<Teams, std::io::Error> {
+    let config = &state.config;
+    let mut settled = Vec::new();
+    for path in changed {
+        // Removed files are left out of the full read anyway
+        let Some(file) = relative_team_file(team_dir, path).filter(|_| path.exists()) else {
+            continue;
+        };
+        match read_settled_team_file(path, config, &state.sprite_aliases).await {
+            Ok(team) => settled.push((team_key(&file), team)),
+            Err(_) => state.metrics.record_read_error(),
+        }
+    }
+
+    let mut teams = state.read_teams()?;
+    for (key, mut team) in settled {
+        // Ignored files aren't part of the teams
+        if let Some(entry) = teams.get_mut(&key) {
+            set_sprite_urls(config, &state.sprite_aliases, &key, &mut team);
+            *entry = team;
+        }
+    }
+    Ok(teams)
+}
+
+/// Start watching `path`, retrying with backoff since antivirus or cloud
+/// sync tools can briefly lock it.
+async fn watch_with_retry(
+    watcher: &mut notify::RecommendedWatcher,
+    path: &path::Path,
+    mode: RecursiveMode,
+) -> notify::Result<()> {
+    const ATTEMPTS: u32 = 5;
+
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 1;
+    loop {
+        match watcher.watch(path, mode) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < ATTEMPTS => {
+                warn!(
+                    "Failed to watch {} (attempt {}/{}): {}, retrying in {:?}",
+                    path.display(),
+                    attempt,
+                    ATTEMPTS,
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Read a team file until two reads `--write-settle-ms` apart agree, giving
+/// up after a few attempts on a file that keeps changing.
+async fn read_settled_team_file(
+    path: &path::Path,
+    config: &Config,
+    aliases: &SpriteAliases,
+) -> Result<PokemonTeam, std::io::Error> {
+    const MAX_READS: usize = 5;
+
+    let settle = Duration::from_millis(config.write_settle_ms);
+    let mut team = read_team_file(path, config, aliases)?;
+    for _ in 1..MAX_READS {
+        tokio::time::sleep(settle).await;
+        let reread = read_team_file(path, config, aliases)?;
+        if reread == team {
+            return Ok(team);
+        }
+        team = reread;
+    }
+
+    warn!(
+        "{} is still changing, sending its latest content",
+        path.display()
+    );
+    Ok(team)
+}
+
+fn fetch_missing_sprites<'a>(state: &AppState, teams: impl IntoIterator<Item = &'a PokemonTeam>) {
+    if !state.config.fetch_sprites {
+        return;
+    }
+
+    // Aliased names are fetched (and saved) under the sprite they map to
+    let names = teams
+        .into_iter()
+        .flat_map(|team| &team.pokemon)
+        .filter(|pokemon| pokemon.sprite_data.is_none())
+        .map(|pokemon| state.sprite_aliases.resolve(&pokemon.name).to_string())
+        .collect();
+    tokio::spawn(pokeapi::fetch_missing_sprites(names));
+}
+
+const TEAM_FILE_EXTENSIONS: [&str; 4] = ["txt", "toml", "yaml", "yml"];
+/// Gitignore-style patterns for files in the team directory that aren't teams
+const IGNORE_FILE: &str = ".overlayignore";
+
+fn is_team_file_name(config: &Config, file_name: &str) -> bool {
+    // An explicit allowlist replaces the name heuristic
+    if !config.team_files.is_empty() {
+        return config.team_files.iter().any(|file| file == file_name);
+    }
+
+    // Editor swap, backup and temp files written while saving
+    let is_editor_file = file_name.starts_with('.')
+        || file_name.ends_with('~')
+        || file_name.ends_with(".swp")
+        || file_name.ends_with(".tmp");
+
+    !is_editor_file
+        && file_name.contains("team")
+        && file_name
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| TEAM_FILE_EXTENSIONS.contains(&ext))
+}
+
+fn get_team_files(config: &Config) -> Result<Vec<String>, std::io::Error> {
+    let mut files = Vec::new();
+    collect_team_files(config, &config.team_dir, "", &mut files)?;
+    let ignore = team_ignore(config);
+    files.retain(|file| !is_ignored(&ignore, file));
+    Ok(files)
+}
+
+/// Patterns from the team directory's `.overlayignore`, matching nothing if
+/// there is none.
+fn team_ignore(config: &Config) -> Gitignore {
+    let (ignore, error) = Gitignore::new(config.team_dir.join(IGNORE_FILE));
+    if let Some(e) = error {
+        warn!("Invalid pattern in {}: {}", IGNORE_FILE, e);
+    }
+    ignore
+}
+
+/// Whether a team file, relative to the team directory, or one of its
+/// directories matches `.overlayignore`.
+fn is_ignored(ignore: &Gitignore, file: &str) -> bool {
+    ignore.matched_path_or_any_parents(file, false).is_ignore()
+}
+
+/// Add the team files in `dir` to `files`, as paths relative to the team
+/// directory. Sub-directories are only searched with `--recursive`.
+fn collect_team_files(
+    config: &Config,
+    dir: &path::Path,
+    prefix: &str,
+    files: &mut Vec<String>,
+) -> Result<(), std::io::Error> {
+    // Skip entries we can't read instead of failing the whole listing
+    for entry in fs::read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skipping unreadable entry in {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        let file_name = match entry.file_name().into_string() {
+            Ok(file_name) => file_name,
+            Err(file_name) => {
+                warn!("Skipping non UTF-8 file name: {:?}", file_name);
+                continue;
+            }
+        };
+
+        // `DirEntry::file_type` doesn't follow symlinks, so symlinked
+        // directories (which could loop) aren't descended into
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        if is_dir {
+            if config.recursive && !file_name.starts_with('.') {
+                let prefix = format!("{}{}/", prefix, file_name);
+                if let Err(e) = collect_team_files(config, &entry.path(), &prefix, files) {
+                    warn!(
+                        "Skipping unreadable directory {}: {}",
+                        entry.path().display(),
+                        e
+                    );
+                }
+            }
+            continue;
+        }
+
+        if entry.path().is_file() && is_team_file_name(config, &file_name) {
+            files.push(format!("{}{}", prefix, file_name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Key a team is sent under: the file name without its extension
+/// (`my.team.v2.txt` is `my.team.v2`), prefixed with its sub-directories
+/// joined by `-` (`runs/2024/team.txt` is `runs-2024-team`).
+fn team_key(file: &str) -> String {
+    let (dirs, file_name) = file.rsplit_once('/').unwrap_or(("", file));
+    let stem = path::Path::new(file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(file_name);
+    if dirs.is_empty() {
+        stem.to_string()
+    } else {
+        format!("{}-{}", dirs.replace('/', "-"), stem)
+    }
+}
+
+/// Path of a changed file relative to the team directory, in the form
+/// `get_team_files` lists it.
+fn relative_team_file(team_dir: &path::Path, path: &path::Path) -> Option<String> {
+    let relative = path.strip_prefix(team_dir).ok()?;
+    let parts = relative
+        .iter()
+        .map(|part| part.to_str())
+        .collect::<Option<Vec<_>>>()?;
+    // Hidden directories aren't listed, so ignore changes inside them too
+    let (_, dirs) = parts.split_last()?;
+    if dirs.iter().any(|dir| dir.starts_with('.')) {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+fn read_team_files(config: &Config, aliases: &SpriteAliases) -> Result<Teams, std::io::Error> {
+    let files = get_team_files(config)?;
+
+    debug!("Reading team files: {:?}", files);
+
+    let mut teams = Teams::new();
+
+    for file in files {
+        let mut team = read_team_file(&config.team_dir.join(&file), config, aliases)?;
+        let key = team_key(&file);
+        set_sprite_urls(config, aliases, &key, &mut team);
+        teams.insert(key, team);
+    }
+
+    // Directory listings have no set order, so sort to keep team switchers
+    // from jumping around
+    match config.sort_teams {
+        TeamOrder::Name => teams.sort_keys(),
+        // Most recently edited first
+        TeamOrder::Mtime => teams.sort_by(|key_a, a, key_b, b| {
+            b.modified_at
+                .cmp(&a.modified_at)
+                .then_with(|| key_a.cmp(key_b))
+        }),
+    }
+
+    Ok(teams)
+}
+
+/// Fill in `sprite_url` for every Pokemon in a team, so overlays don't have
+/// to work out sprite file names themselves.
+fn set_sprite_urls(config: &Config, aliases: &SpriteAliases, key: &str, team: &mut PokemonTeam) {
+    for pokemon in &mut team.pokemon {
+        if !pokemon.name.is_empty() {
+            pokemon.sprite_url = Some(sprites::sprite_url(config, aliases, key, &pokemon.name));
+        }
+    }
+}
+
+fn read_team_file(
+    file: &path::Path,
+    config: &Config,
+    aliases: &SpriteAliases,
+) -> Result<PokemonTeam, std::io::Error> {
+    let content = read_team_content(file)?;
+    let extension = file.extension().and_then(|ext| ext.to_str());
+    let source = file.display().to_string();
+    let mut team = parse_team_file(&content, extension, &source, config, aliases)?;
+
+    team.modified_at = fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_secs());
+    Ok(team)
+}
+
+/// Content of a team file. A stray byte in one file (e.g. a name saved as
+/// Latin-1) shouldn't blank out every team, so decode what can be decoded.
+fn read_team_content(file: &path::Path) -> Result<String, std::io::Error> {
+    match String::from_utf8(fs::read(file)?) {
+        Ok(content) => Ok(content),
+        Err(e) => {
+            warn!(
+                "{} isn't valid UTF-8, invalid bytes are replaced with U+FFFD",
+                file.display()
+            );
+            Ok(String::from_utf8_lossy(e.as_bytes()).into_owned())
+        }
+    }
+}
+
+/// How the Pokemon in a team file are written down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TeamFormat {
+    /// One Pokemon per line, see `parse_team_lines`
+    Lines,
+    /// A Pokemon Showdown export
+    Showdown,
+    Toml,
+    Yaml,
+}
+
+impl TeamFormat {
+    /// Structured files map straight onto PokemonTeam, text files are either
+    /// a Showdown export or the plain line format.
+    fn detect(extension: Option<&str>, content: &str) -> Self {
+        match extension {
+            Some("toml") => TeamFormat::Toml,
+            Some("yaml") | Some("yml") => TeamFormat::Yaml,
+            _ if showdown::is_showdown_paste(content) => TeamFormat::Showdown,
+            _ => TeamFormat::Lines,
+        }
+    }
+}
+
+/// Parse the content of a team file with the given extension, as the server
+/// would send it. `source` names it in warnings.
+fn parse_team_file(
+    content: &str,
+    extension: Option<&str>,
+    source: &str,
+    config: &Config,
+    aliases: &SpriteAliases,
+) -> Result<PokemonTeam, std::io::Error> {
+    // Notepad saves with a UTF-8 BOM which would end up in the first name.
+    // CRLF line endings are handled by the `trim` on each line.
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let format = TeamFormat::detect(extension, content);
+    parse_team_as(content, format, source, config, aliases)
+}
+
+/// Parse team file content in a known format, see `parse_team_file`.
+fn parse_team_as(
+    content: &str,
+    format: TeamFormat,
+    source: &str,
+    config: &Config,
+    aliases: &SpriteAliases,
+) -> Result<PokemonTeam, std::io::Error> {
+    let team_size = config.team_size.into();
+
+    let invalid_data = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let mut pokemon = match format {
+        TeamFormat::Toml => {
+            toml::from_str::<PokemonTeam>(content)
+                .map_err(|e| invalid_data(e.to_string()))?
+                .pokemon
+        }
+        TeamFormat::Yaml => {
+            serde_yaml::from_str::<PokemonTeam>(content)
+                .map_err(|e| invalid_data(e.to_string()))?
+                .pokemon
+        }
+        TeamFormat::Showdown => showdown::parse(content),
+        TeamFormat::Lines => parse_team_lines(content, config.csv_line),
+    };
+    for pokemon in &mut pokemon {
+        normalize_pokemon(pokemon, source, aliases);
+    }
+    pokemon.retain(|pokemon| !pokemon.name.is_empty());
+    pokemon.truncate(team_size); // Only take the first team_size Pokemon
+
+    // Pad with empty slots if less than team_size
+    while !config.no_pad && pokemon.len() < team_size {
+        pokemon.push(Pokemon {
+            empty: true,
+            ..Default::default()
+        });
+    }
+
+    Ok(PokemonTeam {
+        pokemon,
+        modified_at: None,
+    })
+}
+
+/// Clean up a Pokemon as read from a team file (or sent to be saved in one)
+/// and fill in what the server works out itself.
+fn normalize_pokemon(pokemon: &mut Pokemon, source: &str, aliases: &SpriteAliases) {
+    // Stray whitespace would break the sprite lookup
+    pokemon.name = pokemon.name.trim().to_string();
+    pokemon.sprite_data = pokemon.sprite_data.take().and_then(|data| {
+        utils::image_data_url(&data, MAX_INLINE_SPRITE_SIZE)
+            .inspect_err(|e| {
+                warn!(
+                    "Ignoring inline sprite for {:?} in {}: {}",
+                    pokemon.name, source, e
+                )
+            })
+            .ok()
+    });
+    if !pokemon.name.is_empty() && !utils::is_plausible_name(&pokemon.name) {
+        warn!("Suspicious Pokemon name {:?} in {}", pokemon.name, source);
+        pokemon.suspicious_name = true;
+    }
+    if pokemon.types.is_empty() {
+        pokemon.types = types::lookup(aliases, &pokemon.name).unwrap_or_default();
+    }
+}
+
+/// Write Pokemon out in a team file format, the inverse of `parse_team_as`.
+fn format_team_as(
+    pokemon: &[Pokemon],
+    format: TeamFormat,
+    aliases: &SpriteAliases,
+) -> Result<String, String> {
+    match format {
+        TeamFormat::Lines => Ok(pokemon
+            .iter()
+            .map(|pokemon| format_team_line(pokemon) + "\n")
+            .collect()),
+        TeamFormat::Showdown => Ok(showdown::format(pokemon)),
+        TeamFormat::Toml => {
+            toml::to_string(&structured_team(pokemon, aliases)).map_err(|e| e.to_string())
+        }
+        TeamFormat::Yaml => {
+            serde_yaml::to_string(&structured_team(pokemon, aliases)).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// A team as written to TOML and YAML files: without what the server fills
+/// in, unset fields or the types it would look up anyway.
+fn structured_team(pokemon: &[Pokemon], aliases: &SpriteAliases) -> serde_json::Value {
+    let pokemon: Vec<serde_json::Value> = pokemon
+        .iter()
+        .map(|pokemon| {
+            let mut value = serde_json::to_value(pokemon).expect("Pokemon serializes");
+            if let serde_json::Value::Object(fields) = &mut value {
+                fields.retain(|field, value| {
+                    !matches!(field.as_str(), "suspicious_name" | "sprite_url" | "empty")
+                        && !value.is_null()
+                        && *value != serde_json::json!([])
+                });
+                if types::lookup(aliases, &pokemon.name).as_ref() == Some(&pokemon.types) {
+                    fields.remove("types");
+                }
+            }
+            value
+        })
+        .collect();
+    serde_json::json!({ "pokemon": pokemon })
+}
+
+/// Parse the plain format, one Pokemon per line:
+///
+/// ```text
+/// name[:nickname[:field...]]
+/// ```
+///
+/// Everything before the first colon is the name. Optional fields are read
+/// from the end of the line while they match one of these tokens:
+///
+/// - `L<level>` (e.g. `L50`) for the level
+/// - `M` or `F` for the gender
+/// - `shiny` to mark the Pokemon as shiny
+/// - `HP<current>/<max>` (e.g. `HP45/120`) for the remaining HP
+/// - one of `PAR`, `PSN`, `TOX`, `BRN`, `SLP`, `FRZ` or `FNT` for the status
+///   condition. Fainted Pokemon are still sent so overlays can gray them out.
+/// - `sprite=<data URL or base64>` to embed the sprite in the file. It has to
+///   be the last field since data URLs contain colons (and commas, so use
+///   bare base64 with `--csv-line`).
+///
+/// What remains after the name is the nickname, which may itself contain
+/// colons. Leave the nickname empty to only set fields (`pikachu::L50`).
+/// Lines starting with `#` are comments.
+///
+/// With `csv` (`--csv-line`), a line can hold several comma-separated
+/// Pokemon (`pikachu, charizard:Char, blastoise`), so nicknames can't
+/// contain commas.
+fn parse_team_lines(content: &str, csv: bool) -> Vec<Pokemon> {
+    content
+        .lines()
+        .filter(|line| !line.trim().starts_with('#')) // Skip comment lines
+        .flat_map(|line| {
+            if csv {
+                line.split(',').collect()
+            } else {
+                vec![line]
+            }
+        })
+        .map(|entry| parse_team_line(entry.trim()))
+        .filter(|pokemon| !pokemon.name.is_empty())
+        .collect()
+}
+
+fn parse_team_line(line: &str) -> Pokemon {
+    // Split off the inline sprite first, its colons aren't field separators
+    let (line, sprite_data) = match line.split_once(":sprite=") {
+        Some((line, sprite)) => (line, Some(sprite.trim().to_string())),
+        None => (line, None),
+    };
+
+    // Only the first colon separates the name, the nickname may contain more
+    let Some((name, rest)) = line.split_once(':') else {
+        return Pokemon {
+            name: line.to_string(),
+            sprite_data,
+            ..Default::default()
+        };
+    };
+
+    let mut pokemon = Pokemon {
+        name: name.to_string(),
+        sprite_data,
+        ..Default::default()
+    };
+
+    // The first segment is always part of the nickname, so a nickname like
+    // `F` isn't mistaken for a gender
+    let mut segments: Vec<&str> = rest.split(':').collect();
+    while segments.len() > 1 {
+        let field = segments[segments.len() - 1].trim();
+        if field.eq_ignore_ascii_case("shiny") && pokemon.shiny.is_none() {
+            pokemon.shiny = Some(true);
+        } else if (field == "M" || field == "F") && pokemon.gender.is_none() {
+            pokemon.gender = field.chars().next();
+        } else if let Some(level) = field
+            .strip_prefix(['L', 'l'])
+            .and_then(|level| level.parse::<u8>().ok())
+            .filter(|level| pokemon.level.is_none() && (1..=100).contains(level))
+        {
+            pokemon.level = Some(level);
+        } else if let Some((current, max)) = field
+            .strip_prefix(['H', 'h'])
+            .and_then(|hp| hp.strip_prefix(['P', 'p']))
+            .and_then(|hp| hp.split_once('/'))
+            .filter(|_| pokemon.hp_max.is_none())
+        {
+            let (Ok(current), Ok(max)) = (current.parse(), max.parse()) else {
+                break;
+            };
+            pokemon.hp_current = Some(current);
+            pokemon.hp_max = Some(max);
+        } else if STATUS_CONDITIONS.contains(&field.to_uppercase().as_str())
+            && pokemon.status.is_none()
+        {
+            pokemon.status = Some(field.to_uppercase());
+        } else {
+            break;
+        }
+        segments.pop();
+    }
+
+    let nickname = segments.join(":");
+    pokemon.nickname = Some(nickname.trim().to_string()).filter(|n| !n.is_empty());
+    pokemon
+}
+
+/// Inverse of `parse_team_line`.
+fn format_team_line(pokemon: &Pokemon) -> String {
+    let mut line = pokemon.name.clone();
+    let mut fields = Vec::new();
+    if let Some(level) = pokemon.level {
+        fields.push(format!("L{}", level));
+    }
+    if let Some(gender) = pokemon.gender {
+        fields.push(gender.to_string());
+    }
+    if pokemon.shiny == Some(true) {
+        fields.push("shiny".to_string());
+    }
+    if let (Some(current), Some(max)) = (pokemon.hp_current, pokemon.hp_max) {
+        fields.push(format!("HP{}/{}", current, max));
+    }
+    if let Some(status) = &pokemon.status {
+        fields.push(status.clone());
+    }
+
+    if pokemon.nickname.is_some() || !fields.is_empty() {
+        line.push(':');
+        line.push_str(pokemon.nickname.as_deref().unwrap_or_default());
+    }
+    for field in fields {
+        line.push(':');
+        line.push_str(&field);
+    }
+    if let Some(sprite) = &pokemon.sprite_data {
+        line.push_str(":sprite=");
+        line.push_str(sprite);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(["pokemon-team-display"].iter().chain(args))
+    }
+
+    /// Parse a plain team file read with these command line arguments.
+    fn parse(content: &str, args: &[&str]) -> PokemonTeam {
+        let aliases = SpriteAliases::default();
+        parse_team_file(content, Some("txt"), "team.txt", &config(args), &aliases).unwrap()
+    }
+
+    #[test]
+    fn nickname_keeps_extra_colons() {
+        let pokemon = parse_team_line("pikachu:My:Weird:Name");
+        assert_eq!(pokemon.name, "pikachu");
+        assert_eq!(pokemon.nickname.as_deref(), Some("My:Weird:Name"));
+    }
+
+    #[test]
+    fn empty_nickname_is_none() {
+        let pokemon = parse_team_line("pikachu:");
+        assert_eq!(pokemon.name, "pikachu");
+        assert_eq!(pokemon.nickname, None);
+    }
+
+    #[test]
+    fn comment_lines_are_skipped() {
+        let pokemon = parse_team_lines("# Route 3\npikachu\n  # Mt. Moon\ncharizard\n", false);
+        let names: Vec<_> = pokemon
+            .iter()
+            .map(|pokemon| pokemon.name.as_str())
+            .collect();
+        assert_eq!(names, ["pikachu", "charizard"]);
+    }
+
+    #[test]
+    fn all_comment_file_is_padded() {
+        let team = parse("# Route 3\n# Mt. Moon\n", &[]);
+        assert_eq!(team.pokemon.len(), 6);
+        assert!(team.pokemon.iter().all(|pokemon| pokemon.empty));
+    }
+
+    #[test]
+    fn fields_after_nickname() {
+        let pokemon = parse_team_line("pikachu:Sparky:L50:F:shiny");
+        assert_eq!(pokemon.name, "pikachu");
+        assert_eq!(pokemon.nickname.as_deref(), Some("Sparky"));
+        assert_eq!(pokemon.level, Some(50));
+        assert_eq!(pokemon.gender, Some('F'));
+        assert_eq!(pokemon.shiny, Some(true));
+    }
+
+    #[test]
+    fn plain_name_has_no_fields() {
+        assert_eq!(
+            parse_team_line("pikachu"),
+            Pokemon {
+                name: "pikachu".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn fields_without_nickname() {
+        let pokemon = parse_team_line("pikachu::L50");
+        assert_eq!(pokemon.nickname, None);
+        assert_eq!(pokemon.level, Some(50));
+    }
+
+    fn state(config: Config) -> AppState {
+        let (tx, _) = broadcast::channel(config.channel_capacity as usize);
+        AppState {
+            tx,
+            write_limiter: RateLimiter::new(config.max_writes_per_sec),
+            config,
+            last_updated: AtomicU64::new(0),
+            clients: AtomicUsize::new(0),
+            seq: AtomicU64::new(0),
+            sprite_aliases: SpriteAliases::default(),
+            reload: Notify::new(),
+            metrics: Metrics::default(),
+            shutdown: watch::channel(false).1,
+        }
+    }
+
+    #[test]
+    fn unchanged_team_is_broadcast_once() {
+        let config = config(&[]);
+        let state = state(config.clone());
+        let mut rx = state.tx.subscribe();
+        let mut last_sent = Arc::new(Teams::new());
+
+        for _ in 0..2 {
+            let data = parse("pikachu\n", &[]);
+            send_team(&state, &mut last_sent, "team".to_string(), data);
+        }
+        assert!(
+            matches!(&*rx.try_recv().unwrap().update, ServerMsg::Delta { team, .. } if team == "team")
+        );
+        assert!(rx.try_recv().is_err());
+
+        for _ in 0..2 {
+            let teams = Teams::from([("team".to_string(), parse("charizard\n", &[]))]);
+            send_teams(&state, &mut last_sent, teams);
+        }
+        assert!(matches!(
+            &*rx.try_recv().unwrap().update,
+            ServerMsg::FullSync { .. }
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn bom_and_crlf_are_stripped() {
+        let team = parse("\u{feff}pikachu:Sparky\r\ncharizard\r\n", &["--no-pad"]);
+        let names: Vec<_> = team
+            .pokemon
+            .iter()
+            .map(|pokemon| pokemon.name.as_str())
+            .collect();
+        assert_eq!(names, ["pikachu", "charizard"]);
+        assert_eq!(team.pokemon[0].nickname.as_deref(), Some("Sparky"));
+    }
+
+    #[test]
+    fn editor_files_are_not_teams() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "team.txt",
+            "team.txt~",
+            ".team.txt.swp",
+            "team.txt.tmp",
+            "notes.txt",
+        ] {
+            fs::write(dir.path().join(file), "pikachu\n").unwrap();
+        }
+        let config = config(&["--team-dir", dir.path().to_str().unwrap()]);
+
+        assert!(is_team_file_name(&config, "team.txt"));
+        for file in ["team.txt~", ".team.txt.swp", "team.txt.tmp", "notes.txt"] {
+            assert!(!is_team_file_name(&config, file), "{}", file);
+        }
+        assert_eq!(get_team_files(&config).unwrap(), ["team.txt"]);
+    }
+
+    #[test]
+    fn hp_and_status_fields() {
+        let pokemon = parse_team_line("pikachu:Sparky:HP45/120:par");
+        assert_eq!(pokemon.nickname.as_deref(), Some("Sparky"));
+        assert_eq!(pokemon.hp_current, Some(45));
+        assert_eq!(pokemon.hp_max, Some(120));
+        assert_eq!(pokemon.status.as_deref(), Some("PAR"));
+
+        for status in STATUS_CONDITIONS {
+            let pokemon = parse_team_line(&format!("pikachu::{}", status));
+            assert_eq!(pokemon.status.as_deref(), Some(status));
+            assert_eq!(pokemon.nickname, None);
+        }
+    }
+
+    #[test]
+    fn fainted_pokemon_are_kept() {
+        let team = parse("pikachu::HP0/120:FNT\ncharizard\n", &["--no-pad"]);
+        assert_eq!(team.pokemon.len(), 2);
+        assert_eq!(team.pokemon[0].status.as_deref(), Some("FNT"));
+        assert_eq!(team.pokemon[0].hp_current, Some(0));
+    }
+
+    #[test]
+    fn names_are_trimmed() {
+        let team = parse("  pikachu  :Sparky\n\tMr. Mime \n", &["--no-pad"]);
+        assert_eq!(team.pokemon[0].name, "pikachu");
+        assert_eq!(team.pokemon[1].name, "Mr. Mime");
+        assert!(team.pokemon.iter().all(|pokemon| !pokemon.suspicious_name));
+    }
+
+    #[test]
+    fn odd_names_are_suspicious() {
+        let team = parse("pika\tchu\npikachu\u{7}\n", &["--no-pad"]);
+        assert_eq!(team.pokemon.len(), 2);
+        assert!(team.pokemon.iter().all(|pokemon| pokemon.suspicious_name));
+    }
+
+    #[test]
+    fn rename_onto_team_file_is_a_change() {
+        use notify::{
+            event::{ModifyKind, RenameMode},
+            EventKind,
+        };
+
+        let config = config(&[]);
+        let team_dir = path::Path::new("/overlay/teams");
+        let sprites_dir = path::Path::new("/overlay/sprites");
+        let rename = |mode, paths: &[&str]| {
+            let event = Event::new(EventKind::Modify(ModifyKind::Name(mode)));
+            paths
+                .iter()
+                .fold(event, |event, path| event.add_path(team_dir.join(path)))
+        };
+
+        // Reported as one event with both paths, or as two events
+        for events in [
+            vec![rename(RenameMode::Both, &["team.txt.tmp", "team.txt"])],
+            vec![
+                rename(RenameMode::From, &["team.txt.tmp"]),
+                rename(RenameMode::To, &["team.txt"]),
+            ],
+        ] {
+            let mut changes = BurstChanges::default();
+            let relevant: Vec<bool> = events
+                .into_iter()
+                .map(|event| changes.add(event, &config, team_dir, sprites_dir))
+                .collect();
+            assert_eq!(relevant.last(), Some(&true));
+            assert_eq!(changes.files, HashSet::from([team_dir.join("team.txt")]));
+            assert!(!changes.team_dir_moved && !changes.ignore_changed);
+        }
+    }
+
+    #[test]
+    fn padding_slots_are_marked_empty() {
+        let team = parse("pikachu\n", &[]);
+        let padding = serde_json::to_value(&team.pokemon[1]).unwrap();
+        let blank = serde_json::to_value(Pokemon::default()).unwrap();
+        assert_eq!(padding["empty"], true);
+        assert_eq!(blank["empty"], false);
+        assert_ne!(padding, blank);
+    }
+
+    #[test]
+    fn csv_line_mixes_nicknamed_and_plain() {
+        let team = parse(
+            "pikachu:Sparky:L50, charizard,blastoise:Blasty\nmewtwo, mew ,dragonite, gengar\n",
+            &["--csv-line", "--no-pad"],
+        );
+        let names: Vec<_> = team
+            .pokemon
+            .iter()
+            .map(|pokemon| pokemon.name.as_str())
+            .collect();
+        // Capped at --team-size
+        assert_eq!(
+            names,
+            [
+                "pikachu",
+                "charizard",
+                "blastoise",
+                "mewtwo",
+                "mew",
+                "dragonite"
+            ]
+        );
+        assert_eq!(team.pokemon[0].nickname.as_deref(), Some("Sparky"));
+        assert_eq!(team.pokemon[0].level, Some(50));
+        assert_eq!(team.pokemon[1].nickname, None);
+        assert_eq!(team.pokemon[2].nickname.as_deref(), Some("Blasty"));
+    }
+
+    #[test]
+    fn team_key_drops_only_the_extension() {
+        assert_eq!(team_key("my.team.v2.txt"), "my.team.v2");
+        assert_eq!(team_key("team.txt"), "team");
+        assert_eq!(team_key("runs/2024/team.txt"), "runs-2024-team");
+    }
+
+    #[test]
+    fn team_key_handles_odd_names() {
+        for (file, expected) in [
+            ("", ""),
+            ("team", "team"),
+            (".team.txt", ".team"),
+            ("team.", "team"),
+            ("runs/", "runs-"),
+            ("runs//team.txt", "runs--team"),
+            ("チーム.txt", "チーム"),
+        ] {
+            assert_eq!(team_key(file), expected, "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_file_is_read_lossily() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("team.txt"), "pikachu\n").unwrap();
+        // `Flabébé` saved as Latin-1
+        fs::write(
+            dir.path().join("other-team.txt"),
+            b"Flab\xe9b\xe9\ncharizard\n",
+        )
+        .unwrap();
+        let config = config(&["--team-dir", dir.path().to_str().unwrap(), "--no-pad"]);
+
+        let teams = read_team_files(&config, &SpriteAliases::default()).unwrap();
+        assert_eq!(teams["team"].pokemon[0].name, "pikachu");
+        let names: Vec<_> = teams["other-team"]
+            .pokemon
+            .iter()
+            .map(|pokemon| pokemon.name.as_str())
+            .collect();
+        assert_eq!(names, ["Flab\u{fffd}b\u{fffd}", "charizard"]);
+    }
+
+    #[test]
+    fn overlayignore_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub_dir in ["archive", "runs"] {
+            fs::create_dir(dir.path().join(sub_dir)).unwrap();
+        }
+        for file in [
+            "team.txt",
+            "old-team.txt",
+            "old-keep-team.txt",
+            "archive/team.txt",
+            "runs/team.txt",
+        ] {
+            fs::write(dir.path().join(file), "pikachu\n").unwrap();
+        }
+        fs::write(
+            dir.path().join(IGNORE_FILE),
+            "old-*.txt\n!old-keep-team.txt\narchive/\n",
+        )
+        .unwrap();
+        let config = config(&["--team-dir", dir.path().to_str().unwrap(), "--recursive"]);
+
+        let mut files = get_team_files(&config).unwrap();
+        files.sort();
+        assert_eq!(files, ["old-keep-team.txt", "runs/team.txt", "team.txt"]);
+    }
+
+    #[test]
+    fn renamed_pokemon_saves_with_stale_types() {
+        let config = config(&[]);
+        let aliases = SpriteAliases::default();
+        // As sent by the editor after changing the species of a row
+        let pokemon = [Pokemon {
+            name: "bulbasaur".to_string(),
+            nickname: Some("Sparky".to_string()),
+            types: vec!["fire".to_string(), "flying".to_string()],
+            ..Default::default()
+        }];
+
+        let content = format_team_for_save(
+            &pokemon,
+            "team.txt",
+            &[TeamFormat::Lines],
+            &config,
+            &aliases,
+        )
+        .unwrap();
+        assert_eq!(content, "bulbasaur:Sparky\n");
+        let content = format_team_for_save(
+            &pokemon,
+            "team.txt",
+            &[TeamFormat::Showdown, TeamFormat::Lines],
+            &config,
+            &aliases,
+        );
+        assert!(content.is_some());
+    }
 }
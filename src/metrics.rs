@@ -0,0 +1,87 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, Response},
+};
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::AppState;
+
+/// Counters for `/metrics` (`--metrics`) that `AppState` doesn't already
+/// keep.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    team_read_errors: AtomicU64,
+    /// How long the last read of every team file took
+    last_read_micros: AtomicU64,
+}
+
+impl Metrics {
+    /// Record a read of every team file.
+    pub fn record_read(&self, elapsed: Duration, ok: bool) {
+        self.last_read_micros
+            .store(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if !ok {
+            self.record_read_error();
+        }
+    }
+
+    pub fn record_read_error(&self) {
+        self.team_read_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Prometheus text format, so scrapers can alert when the overlay stops
+/// updating.
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> Response<Body> {
+    let metrics = &state.metrics;
+    let mut body = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} {}", name, kind);
+        let _ = writeln!(body, "{} {}", name, value);
+    };
+
+    metric(
+        "overlay_broadcasts_total",
+        "counter",
+        "Updates broadcast to clients.",
+        state.seq.load(Ordering::Relaxed).to_string(),
+    );
+    metric(
+        "overlay_websocket_clients",
+        "gauge",
+        "Connected WebSocket clients.",
+        state.clients.load(Ordering::Relaxed).to_string(),
+    );
+    metric(
+        "overlay_team_read_errors_total",
+        "counter",
+        "Team file reads that failed.",
+        metrics.team_read_errors.load(Ordering::Relaxed).to_string(),
+    );
+    metric(
+        "overlay_last_read_duration_seconds",
+        "gauge",
+        "Time the last read of every team file took.",
+        (metrics.last_read_micros.load(Ordering::Relaxed) as f64 / 1e6).to_string(),
+    );
+    metric(
+        "overlay_last_update_timestamp_seconds",
+        "gauge",
+        "When teams were last broadcast, in unix seconds.",
+        (state.last_updated() as f64 / 1e3).to_string(),
+    );
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(body))
+        .unwrap()
+}
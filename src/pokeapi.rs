@@ -0,0 +1,54 @@
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::{sprites, utils, SPRITES_DIR};
+
+const POKEAPI_URL: &str = "https://pokeapi.co/api/v2/pokemon";
+
+type FetchError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Download official artwork from PokeAPI for every name that has no sprite
+/// yet. Failures are only logged, the sprite handler falls back to the
+/// placeholder.
+pub async fn fetch_missing_sprites(names: Vec<String>) {
+    let client = reqwest::Client::new();
+
+    for name in names {
+        if name.is_empty() || sprites::find_sprite(&name).is_some() {
+            continue;
+        }
+
+        match fetch_sprite(&client, &name).await {
+            Ok(()) => info!("Downloaded sprite for '{}'", name),
+            Err(e) => warn!("Failed to fetch sprite for '{}': {}", name, e),
+        }
+    }
+}
+
+async fn fetch_sprite(client: &reqwest::Client, name: &str) -> Result<(), FetchError> {
+    let url = format!("{}/{}", POKEAPI_URL, utils::slug(name));
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let pokemon: serde_json::Value = serde_json::from_str(&body)?;
+
+    let sprite_url = pokemon["sprites"]["other"]["official-artwork"]["front_default"]
+        .as_str()
+        .ok_or("no official artwork")?;
+    let sprite = client
+        .get(sprite_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    // Saved under the slug, which is what the sprite lookup matches
+    let file = Path::new(SPRITES_DIR).join(format!("{}.png", utils::slug(name)));
+    tokio::fs::write(file, sprite).await?;
+    Ok(())
+}
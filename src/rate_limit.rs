@@ -0,0 +1,60 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, Response, StatusCode},
+    middleware::Next,
+};
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+use crate::{json_error, AppState};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Global limit on how many requests are accepted per second.
+pub struct RateLimiter {
+    limit: u32,
+    /// Start of the current window and the requests accepted in it
+    window: Mutex<(Instant, u32)>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Count a request, returning false if the limit for this second has
+    /// already been reached.
+    pub fn try_acquire(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if window.0.elapsed() >= WINDOW {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= self.limit {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
+
+/// Reject writes over `--max-writes-per-sec` with 429. Only applied to
+/// routes that modify team files or trigger a broadcast.
+pub async fn limit_writes(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    if state.write_limiter.try_acquire() {
+        return next.run(request).await;
+    }
+
+    let mut response = json_error(StatusCode::TOO_MANY_REQUESTS, "Too many writes, slow down");
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, header::HeaderValue::from_static("1"));
+    response
+}
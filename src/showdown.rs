@@ -0,0 +1,213 @@
+use crate::{Pokemon, Stats};
+
+/// Whether the content looks like a Pokemon Showdown team export rather than
+/// the plain `name:nickname` format. Comment lines don't count, and neither
+/// does an ` @ ` in a line with colons, which can only be a nickname.
+pub fn is_showdown_paste(content: &str) -> bool {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .any(|line| {
+            line.starts_with("Ability:")
+                || line.starts_with("EVs:")
+                || line.starts_with("- ")
+                || (line.contains(" @ ") && !line.contains(':'))
+        })
+}
+
+/// Parse a Showdown export. Each set is separated by a blank line and starts
+/// with a `Nickname (Species) (M) @ Item` header line.
+pub fn parse(content: &str) -> Vec<Pokemon> {
+    let mut team = Vec::new();
+    let mut current: Option<Pokemon> = None;
+
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            team.extend(current.take());
+            continue;
+        }
+
+        match current.as_mut() {
+            None => current = Some(parse_header(line)),
+            Some(pokemon) => {
+                if let Some(ability) = line.strip_prefix("Ability:") {
+                    pokemon.ability = Some(ability.trim().to_string());
+                } else if let Some(level) = line.strip_prefix("Level:") {
+                    pokemon.level = level.trim().parse().ok();
+                } else if let Some(shiny) = line.strip_prefix("Shiny:") {
+                    pokemon.shiny = Some(shiny.trim().eq_ignore_ascii_case("yes"));
+                } else if let Some(evs) = line.strip_prefix("EVs:") {
+                    pokemon.evs = Some(parse_stats(evs));
+                } else if let Some(ivs) = line.strip_prefix("IVs:") {
+                    pokemon.ivs = Some(parse_stats(ivs));
+                } else if let Some(nature) = line.strip_suffix(" Nature") {
+                    pokemon.nature = Some(nature.trim().to_string());
+                } else if let Some(name) = line.strip_prefix("- ") {
+                    pokemon.moves.push(name.trim().to_string());
+                }
+            }
+        }
+    }
+    team.extend(current);
+
+    team.retain(|pokemon| !pokemon.name.is_empty());
+    team
+}
+
+fn parse_header(line: &str) -> Pokemon {
+    let (line, item) = match line.split_once(" @ ") {
+        Some((line, item)) => (line.trim(), Some(item.trim().to_string())),
+        None => (line, None),
+    };
+
+    // Gender marker comes after the species
+    let (line, gender) = match line.strip_suffix(" (M)") {
+        Some(line) => (line, Some('M')),
+        None => match line.strip_suffix(" (F)") {
+            Some(line) => (line, Some('F')),
+            None => (line, None),
+        },
+    };
+
+    // `Nickname (Species)` or just `Species`
+    let (name, nickname) = match line.strip_suffix(')').and_then(|l| l.rsplit_once(" (")) {
+        Some((nickname, species)) => (species, Some(nickname.trim().to_string())),
+        None => (line, None),
+    };
+
+    Pokemon {
+        name: name.trim().to_string(),
+        nickname,
+        item,
+        gender,
+        ..Default::default()
+    }
+}
+
+/// Parse a spread like `252 Atk / 4 SpD / 252 Spe`.
+fn parse_stats(line: &str) -> Stats {
+    let mut stats = Stats::default();
+    for part in line.split('/') {
+        let Some((value, stat)) = part.trim().split_once(' ') else {
+            continue;
+        };
+        let Ok(value) = value.parse() else {
+            continue;
+        };
+        let slot = match stat.trim().to_lowercase().as_str() {
+            "hp" => &mut stats.hp,
+            "atk" => &mut stats.atk,
+            "def" => &mut stats.def,
+            "spa" => &mut stats.spa,
+            "spd" => &mut stats.spd,
+            "spe" => &mut stats.spe,
+            _ => continue,
+        };
+        *slot = Some(value);
+    }
+    stats
+}
+
+/// Format a team as a Showdown export, the inverse of `parse`. Empty slots
+/// are left out.
+pub fn format(team: &[Pokemon]) -> String {
+    let sets: Vec<String> = team
+        .iter()
+        .filter(|pokemon| !pokemon.name.is_empty())
+        .map(format_set)
+        .collect();
+    sets.join("\n")
+}
+
+fn format_set(pokemon: &Pokemon) -> String {
+    let mut header = match &pokemon.nickname {
+        Some(nickname) => format!("{} ({})", nickname, pokemon.name),
+        None => pokemon.name.clone(),
+    };
+    if let Some(gender) = pokemon.gender {
+        header.push_str(&format!(" ({})", gender));
+    }
+    if let Some(item) = &pokemon.item {
+        header.push_str(&format!(" @ {}", item));
+    }
+
+    let mut lines = vec![header];
+    if let Some(ability) = &pokemon.ability {
+        lines.push(format!("Ability: {}", ability));
+    }
+    if let Some(level) = pokemon.level {
+        lines.push(format!("Level: {}", level));
+    }
+    if pokemon.shiny == Some(true) {
+        lines.push("Shiny: Yes".to_string());
+    }
+    if let Some(evs) = &pokemon.evs {
+        lines.push(format!("EVs: {}", format_stats(evs)));
+    }
+    if let Some(nature) = &pokemon.nature {
+        lines.push(format!("{} Nature", nature));
+    }
+    if let Some(ivs) = &pokemon.ivs {
+        lines.push(format!("IVs: {}", format_stats(ivs)));
+    }
+    for name in &pokemon.moves {
+        lines.push(format!("- {}", name));
+    }
+
+    let mut set = lines.join("\n");
+    set.push('\n');
+    set
+}
+
+/// Inverse of `parse_stats`.
+fn format_stats(stats: &Stats) -> String {
+    let stats = [
+        (stats.hp, "HP"),
+        (stats.atk, "Atk"),
+        (stats.def, "Def"),
+        (stats.spa, "SpA"),
+        (stats.spd, "SpD"),
+        (stats.spe, "Spe"),
+    ];
+    let parts: Vec<String> = stats
+        .iter()
+        .filter_map(|&(value, name)| Some(format!("{} {}", value?, name)))
+        .collect();
+    parts.join(" / ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn showdown_export_is_detected() {
+        assert!(is_showdown_paste(
+            "Sparky (Pikachu) @ Light Ball\nAbility: Static\n"
+        ));
+        assert!(is_showdown_paste("Pikachu @ Light Ball\n"));
+    }
+
+    #[test]
+    fn comments_are_not_showdown_markers() {
+        let content = "# caught @ route 3\npikachu\ncharizard\n";
+        assert!(!is_showdown_paste(content));
+    }
+
+    #[test]
+    fn at_sign_in_nickname_is_not_an_item() {
+        assert!(!is_showdown_paste("pikachu:Me @ home\n"));
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let team = parse("# caught @ route 3\nPikachu @ Light Ball\n- Thunderbolt\n");
+        assert_eq!(team.len(), 1);
+        assert_eq!(team[0].name, "Pikachu");
+        assert_eq!(team[0].item.as_deref(), Some("Light Ball"));
+    }
+}
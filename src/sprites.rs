@@ -0,0 +1,368 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, Response, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    path::{self, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use tracing::warn;
+
+use crate::{config::Config, utils, AppState, Assets, Teams, SPRITES_DIR};
+
+/// Sprite extensions, in the order they are tried without --sprite-ext-order
+pub const SPRITE_EXTENSIONS: [&str; 4] = ["png", "gif", "jpg", "webp"];
+pub const PLACEHOLDER: &str = "unknown.png";
+const ALIASES_FILE: &str = "sprites-aliases.toml";
+const DEFAULT_ALIASES: &str = include_str!("../sprites-aliases.toml");
+// Sprites rarely change, so let OBS keep them across reconnects and
+// revalidate with the ETag afterwards
+const SPRITE_CACHE_CONTROL: &str = "public, max-age=300";
+
+pub async fn sprite_handler(
+    State(state): State<Arc<AppState>>,
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let dirs = [path::Path::new(SPRITES_DIR)];
+    serve_sprite_from(&state, &dirs, &file, &headers).await
+}
+
+/// Sprites for a team with its own directory (--sprites-map), falling back
+/// to the shared sprites directory.
+pub async fn team_sprite_handler(
+    State(state): State<Arc<AppState>>,
+    Path((team, file)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let dirs = team_sprite_dirs(&state.config, &team);
+    serve_sprite_from(&state, &dirs, &file, &headers).await
+}
+
+/// Serve the sprite from the first directory that has it.
+async fn serve_sprite_from(
+    state: &AppState,
+    dirs: &[&path::Path],
+    file: &str,
+    headers: &HeaderMap,
+) -> Response<Body> {
+    let ext_order = &state.config.sprite_ext_order;
+    if let Some(path) = find_aliased_sprite(dirs, &state.sprite_aliases, ext_order, file) {
+        if let Ok(response) = serve_sprite(&path, headers).await {
+            return response;
+        }
+    }
+
+    // Serve a placeholder rather than a broken image. It isn't cached so the
+    // real sprite shows up as soon as it is added.
+    let placeholder = Assets::get(PLACEHOLDER).expect("Placeholder sprite is embedded");
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, utils::content_type(PLACEHOLDER))
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(placeholder.data))
+        .unwrap()
+}
+
+async fn serve_sprite(path: &path::Path, headers: &HeaderMap) -> std::io::Result<Response<Body>> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let modified = metadata.modified()?;
+    // HTTP dates only have second precision
+    let modified_secs = modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let modified = UNIX_EPOCH + Duration::from_secs(modified_secs);
+
+    let etag = format!("\"{:x}-{:x}\"", modified_secs, metadata.len());
+    let last_modified = httpdate::fmt_http_date(modified);
+
+    let not_modified = match headers.get(header::IF_NONE_MATCH) {
+        Some(if_none_match) => if_none_match
+            .to_str()
+            .is_ok_and(|value| value.split(',').any(|tag| tag.trim() == etag)),
+        None => headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+            .is_some_and(|since| modified <= since),
+    };
+
+    let builder = Response::builder()
+        .header(header::CACHE_CONTROL, SPRITE_CACHE_CONTROL)
+        .header(header::ETAG, etag)
+        .header(header::LAST_MODIFIED, last_modified);
+
+    if not_modified {
+        return Ok(builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let data = tokio::fs::read(path).await?;
+    Ok(builder
+        .status(StatusCode::OK)
+        .header(
+            header::CONTENT_TYPE,
+            utils::content_type(&path.to_string_lossy()),
+        )
+        .body(Body::from(data))
+        .unwrap())
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpriteEntry {
+    file: String,
+    name: String,
+}
+
+/// List the sprite files so clients can tell which Pokemon have art.
+pub async fn sprite_index_handler() -> Json<Vec<SpriteEntry>> {
+    let mut sprites: Vec<SpriteEntry> = fs::read_dir(SPRITES_DIR)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|file| {
+            let (name, ext) = file.rsplit_once('.')?;
+            if !SPRITE_EXTENSIONS.contains(&ext.to_lowercase().as_str()) {
+                return None;
+            }
+            Some(SpriteEntry {
+                name: name.to_string(),
+                file,
+            })
+        })
+        .collect();
+    sprites.sort_by(|a, b| a.file.cmp(&b.file));
+
+    Json(sprites)
+}
+
+/// Log every Pokemon in the teams that has no sprite file, looking in the
+/// team's own sprite directory first if it has one.
+pub fn warn_missing_sprites(config: &Config, aliases: &SpriteAliases, teams: &Teams) {
+    let mut names: Vec<(&str, &str)> = teams
+        .iter()
+        .flat_map(|(key, team)| {
+            team.pokemon
+                .iter()
+                // Sprites embedded in the team file aren't looked up
+                .filter(|pokemon| pokemon.sprite_data.is_none())
+                .map(move |pokemon| (pokemon.name.as_str(), key.as_str()))
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+
+    for (name, team) in names {
+        if find_team_sprite(config, aliases, team, name).is_none() {
+            let dir = config
+                .team_sprites_dir(team)
+                .unwrap_or(path::Path::new(SPRITES_DIR));
+            warn!(
+                "Missing sprite for '{}' in '{}' (expected '{}.png')",
+                name,
+                dir.display(),
+                utils::slug(aliases.resolve(name))
+            );
+        }
+    }
+}
+
+/// Look up a sprite for a team, in its own directory (--sprites-map) first
+/// and then the sprites directory.
+pub fn find_team_sprite(
+    config: &Config,
+    aliases: &SpriteAliases,
+    team: &str,
+    file: &str,
+) -> Option<PathBuf> {
+    let dirs = team_sprite_dirs(config, team);
+    find_aliased_sprite(&dirs, aliases, &config.sprite_ext_order, file)
+}
+
+/// URL the overlay loads a Pokemon's sprite from, pointing at the file that
+/// actually exists. Without one it points at where the sprite would be,
+/// which serves the placeholder until it is added (and works with
+/// --api-only, unlike the placeholder's own path).
+pub fn sprite_url(config: &Config, aliases: &SpriteAliases, team: &str, name: &str) -> String {
+    let file = find_team_sprite(config, aliases, team, name)
+        .and_then(|path| path.file_name()?.to_str().map(utils::percent_encode))
+        .unwrap_or_else(|| format!("{}.png", utils::slug(name)));
+
+    // Same base URLs as the ones in `/config`
+    match config.team_sprites_dir(team) {
+        Some(_) => format!("/team-sprites/{}/{}", utils::percent_encode(team), file),
+        None => format!("/sprites/{}", file),
+    }
+}
+
+/// Directories a team's sprites are looked up in: its own (--sprites-map)
+/// and then the sprites directory.
+fn team_sprite_dirs<'a>(config: &'a Config, team: &str) -> Vec<&'a path::Path> {
+    config
+        .team_sprites_dir(team)
+        .into_iter()
+        .chain([path::Path::new(SPRITES_DIR)])
+        .collect()
+}
+
+/// Look up a sprite in the first directory that has it, by its alias and
+/// then by the name itself so sprites named after the Pokemon still work.
+fn find_aliased_sprite(
+    dirs: &[&path::Path],
+    aliases: &SpriteAliases,
+    ext_order: &[String],
+    file: &str,
+) -> Option<PathBuf> {
+    let aliased = aliases.resolve_file(file);
+    let sprite = [&*aliased, file].into_iter().find_map(|file| {
+        dirs.iter()
+            .find_map(|dir| find_sprite_in(dir, file, ext_order))
+    });
+    sprite
+}
+
+/// Look up a sprite in the sprites directory, see `find_sprite_in`.
+pub fn find_sprite(file: &str) -> Option<PathBuf> {
+    find_sprite_in(path::Path::new(SPRITES_DIR), file, &[])
+}
+
+/// Look up a sprite in `dir` by its normalized name (see `utils::slug`).
+/// Extensions are tried in `ext_order` (--sprite-ext-order) if given, which
+/// takes precedence over the requested extension, then the requested one and
+/// then the rest of `SPRITE_EXTENSIONS`.
+fn find_sprite_in(dir: &path::Path, file: &str, ext_order: &[String]) -> Option<PathBuf> {
+    if file.contains('/') || !utils::is_safe_path(file) {
+        return None;
+    }
+
+    let (name, requested_ext) = split_extension(file);
+    let wanted = utils::slug(name);
+
+    let entries: Vec<_> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| {
+            let file = entry.file_name().into_string().ok()?;
+            match split_extension(&file) {
+                (name, Some(ext)) if utils::slug(name) == wanted => Some((ext, entry.path())),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let sprite = ext_order
+        .iter()
+        .cloned()
+        .chain(requested_ext)
+        .chain(SPRITE_EXTENSIONS.map(String::from))
+        .find_map(|ext| {
+            entries
+                .iter()
+                .find(|(found, _)| *found == ext)
+                .map(|(_, path)| path.clone())
+        });
+    sprite
+}
+
+/// Split off a known sprite extension, lowercased. Names like `Mr. Mime`
+/// don't have one.
+fn split_extension(file: &str) -> (&str, Option<String>) {
+    match file.rsplit_once('.') {
+        Some((name, ext)) if SPRITE_EXTENSIONS.contains(&ext.to_lowercase().as_str()) => {
+            (name, Some(ext.to_lowercase()))
+        }
+        _ => (file, None),
+    }
+}
+
+/// Pokemon names mapped to the name of their sprite, for names that don't
+/// match the sprite file (`Alolan Raichu` is shown with `raichu-alola`).
+#[derive(Clone, Debug, Default)]
+pub struct SpriteAliases(HashMap<String, String>);
+
+impl SpriteAliases {
+    /// The built-in aliases, extended or overridden by `sprites-aliases.toml`
+    /// if there is one.
+    pub fn load() -> Self {
+        let mut aliases = Self::default();
+        aliases
+            .extend(DEFAULT_ALIASES)
+            .expect("Built-in sprite aliases are valid");
+
+        match fs::read_to_string(ALIASES_FILE) {
+            Ok(content) => {
+                if let Err(e) = aliases.extend(&content) {
+                    warn!("Ignoring invalid {}: {}", ALIASES_FILE, e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to read {}: {}", ALIASES_FILE, e),
+        }
+        aliases
+    }
+
+    fn extend(&mut self, content: &str) -> Result<(), toml::de::Error> {
+        let aliases: HashMap<String, String> = toml::from_str(content)?;
+        // Keyed by slug so aliases match however the name is written
+        self.0.extend(
+            aliases
+                .into_iter()
+                .map(|(name, sprite)| (utils::slug(&name), sprite)),
+        );
+        Ok(())
+    }
+
+    /// Sprite name for a Pokemon, the name itself if it has no alias.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.0.get(&utils::slug(name)).map_or(name, String::as_str)
+    }
+
+    /// Same as `resolve` for a sprite file name, keeping its extension.
+    pub fn resolve_file<'a>(&self, file: &'a str) -> Cow<'a, str> {
+        let (name, ext) = split_extension(file);
+        match (self.0.get(&utils::slug(name)), ext) {
+            (Some(sprite), Some(ext)) => Cow::Owned(format!("{}.{}", sprite, ext)),
+            (Some(sprite), None) => Cow::Owned(sprite.clone()),
+            (None, _) => Cow::Borrowed(file),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferred_extension_is_served() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["pikachu.gif", "pikachu.png", "Pikachu.webp"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        let find = |file: &str, ext_order: &[&str]| {
+            let ext_order: Vec<String> = ext_order.iter().map(|ext| ext.to_string()).collect();
+            let path = find_sprite_in(dir.path(), file, &ext_order).unwrap();
+            path.file_name().unwrap().to_str().unwrap().to_string()
+        };
+
+        // SPRITE_EXTENSIONS order, then the requested extension, then
+        // --sprite-ext-order
+        assert_eq!(find("pikachu", &[]), "pikachu.png");
+        assert_eq!(find("Pikachu.GIF", &[]), "pikachu.gif");
+        assert_eq!(find("pikachu.gif", &["webp", "png"]), "Pikachu.webp");
+    }
+}
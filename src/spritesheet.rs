@@ -0,0 +1,125 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, Response, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{imageops, DynamicImage, ImageFormat, RgbaImage};
+use std::{io::Cursor, path::PathBuf, sync::Arc};
+use tracing::warn;
+
+use crate::{json_error, sprites, AppState, Assets, Pokemon};
+
+/// Side of the square cell each slot gets in a sprite sheet, in pixels
+pub const CELL_SIZE: u32 = 96;
+
+/// Where a slot's sprite comes from, worked out before rendering so the
+/// blocking part doesn't need the app state.
+enum Slot {
+    /// Padding slot, left transparent
+    Empty,
+    /// `sprite_data` from the team file
+    Inline(String),
+    File(PathBuf),
+    Placeholder,
+}
+
+/// Every sprite of a team side by side in one PNG, one `CELL_SIZE` cell per
+/// slot, so overlays can show them with `background-position` instead of a
+/// request per sprite.
+pub async fn spritesheet_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response<Body> {
+    let teams = match state.read_teams() {
+        Ok(teams) => teams,
+        Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let Some(team) = teams.get(&name) else {
+        return json_error(StatusCode::NOT_FOUND, "Team not found");
+    };
+
+    let slots: Vec<Slot> = team
+        .pokemon
+        .iter()
+        .map(|pokemon| slot(&state, &name, pokemon))
+        .collect();
+    // Decoding and scaling sprites is too slow for the async runtime
+    match tokio::task::spawn_blocking(move || render(&slots)).await {
+        Ok(Ok(png)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "image/png")
+            // Changes with the team, so always revalidate
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Body::from(png))
+            .unwrap(),
+        Ok(Err(e)) => json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn slot(state: &AppState, team: &str, pokemon: &Pokemon) -> Slot {
+    if pokemon.empty || pokemon.name.is_empty() {
+        return Slot::Empty;
+    }
+    if let Some(data) = &pokemon.sprite_data {
+        return Slot::Inline(data.clone());
+    }
+    match sprites::find_team_sprite(&state.config, &state.sprite_aliases, team, &pokemon.name) {
+        Some(path) => Slot::File(path),
+        None => Slot::Placeholder,
+    }
+}
+
+fn render(slots: &[Slot]) -> Result<Vec<u8>, image::ImageError> {
+    // A team with no Pokemon (--no-pad) still gets a valid, empty image
+    let cells = slots.len().max(1) as u32;
+    let mut sheet = RgbaImage::new(CELL_SIZE * cells, CELL_SIZE);
+
+    for (i, slot) in slots.iter().enumerate() {
+        let Some(sprite) = load(slot) else {
+            continue;
+        };
+        // Only shrink, small pixel art sprites would get blurry otherwise
+        let sprite = if sprite.width() > CELL_SIZE || sprite.height() > CELL_SIZE {
+            sprite.resize(CELL_SIZE, CELL_SIZE, imageops::FilterType::Triangle)
+        } else {
+            sprite
+        };
+        // Centered in its cell
+        let x = i as u32 * CELL_SIZE + (CELL_SIZE - sprite.width()) / 2;
+        let y = (CELL_SIZE - sprite.height()) / 2;
+        imageops::overlay(&mut sheet, &sprite.to_rgba8(), x.into(), y.into());
+    }
+
+    let mut png = Vec::new();
+    sheet.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Decode a slot's sprite, falling back to the placeholder when it can't be.
+fn load(slot: &Slot) -> Option<DynamicImage> {
+    let sprite = match slot {
+        Slot::Empty => return None,
+        Slot::Placeholder => return load_placeholder(),
+        // Already checked to be a base64 data URL when the team was parsed
+        Slot::Inline(data_url) => data_url
+            .split_once(";base64,")
+            .and_then(|(_, data)| STANDARD.decode(data).ok())
+            .ok_or_else(|| "invalid data URL".to_string())
+            .and_then(|data| image::load_from_memory(&data).map_err(|e| e.to_string())),
+        Slot::File(path) => image::open(path).map_err(|e| e.to_string()),
+    };
+    match sprite {
+        Ok(sprite) => Some(sprite),
+        Err(e) => {
+            warn!("Failed to decode sprite for the sprite sheet: {}", e);
+            load_placeholder()
+        }
+    }
+}
+
+fn load_placeholder() -> Option<DynamicImage> {
+    let placeholder = Assets::get(sprites::PLACEHOLDER)?;
+    image::load_from_memory(&placeholder.data).ok()
+}
@@ -0,0 +1,62 @@
+use rust_embed::RustEmbed;
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::{sprites::SpriteAliases, utils};
+
+#[derive(RustEmbed)]
+#[folder = "data/"]
+struct Data;
+
+const TYPES_FILE: &str = "pokemon-types.json";
+
+/// Name to types table for Gen 1-9, keyed by slug. Forms are only listed
+/// when their types differ from the base species.
+fn table() -> &'static HashMap<String, Vec<String>> {
+    static TABLE: OnceLock<HashMap<String, Vec<String>>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let file = Data::get(TYPES_FILE).expect("bundled type table is missing");
+        serde_json::from_slice(&file.data).expect("bundled type table is invalid")
+    })
+}
+
+/// Types of a Pokemon, lowercase (`["fire", "flying"]`). Names with a sprite
+/// alias (`Alolan Raichu` is `raichu-alola`) are looked up under it first.
+/// Unlisted forms such as `Charizard-Mega-Y` fall back to the base species.
+pub fn lookup(aliases: &SpriteAliases, name: &str) -> Option<Vec<String>> {
+    lookup_slug(aliases.resolve(name)).or_else(|| lookup_slug(name))
+}
+
+fn lookup_slug(name: &str) -> Option<Vec<String>> {
+    let table = table();
+    let mut slug = utils::slug(name);
+    loop {
+        if let Some(types) = table.get(&slug) {
+            return Some(types.clone());
+        }
+        let (base, _) = slug.rsplit_once('-')?;
+        slug.truncate(base.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliased_names_are_looked_up() {
+        let aliases = SpriteAliases::load();
+        assert_eq!(
+            lookup(&aliases, "Alolan Raichu"),
+            Some(vec!["electric".to_string(), "psychic".to_string()])
+        );
+        assert_eq!(
+            lookup(&aliases, "Mega Charizard X"),
+            Some(vec!["fire".to_string(), "dragon".to_string()])
+        );
+        // Names without an alias still work
+        assert_eq!(
+            lookup(&aliases, "Charizard-Mega-Y"),
+            Some(vec!["fire".to_string(), "flying".to_string()])
+        );
+    }
+}
@@ -1,12 +1,45 @@
 pub fn content_type(path: &str) -> &'static str {
-    match path.rsplit('.').next() {
+    let extension = path.rsplit('.').next().map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
         Some("html") => "text/html; charset=utf-8",
         Some("js")   => "text/javascript; charset=utf-8",
         Some("css")  => "text/css; charset=utf-8",
+        Some("json") => "application/json; charset=utf-8",
         Some("svg")  => "image/svg+xml",
         Some("png")  => "image/png",
         Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif")  => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico")  => "image/x-icon",
+        Some("mp4")  => "video/mp4",
+        Some("webm") => "video/webm",
         Some("woff2") => "font/woff2",
         _ => "application/octet-stream",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_extensions() {
+        for (path, expected) in [
+            ("sprites/pikachu.gif", "image/gif"),
+            ("sprites/pikachu.webp", "image/webp"),
+            ("favicon.ico", "image/x-icon"),
+            ("data/pokemon-types.json", "application/json; charset=utf-8"),
+        ] {
+            assert_eq!(content_type(path), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn extensions_are_case_insensitive() {
+        assert_eq!(content_type("SPRITE.PNG"), "image/png");
+        assert_eq!(content_type("Logo.SVG"), "image/svg+xml");
+        assert_eq!(content_type("Sprite.Png"), "image/png");
+        assert_eq!(content_type("bg.WebP"), "image/webp");
+    }
+}
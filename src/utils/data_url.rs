@@ -0,0 +1,43 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Image types an inline sprite can have, by the bytes their files start with.
+const IMAGE_SIGNATURES: [(&[u8], &str); 4] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"GIF8", "image/gif"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"RIFF", "image/webp"),
+];
+
+/// Check an inline sprite, either a `data:image/...;base64,` URL or bare
+/// base64, and return it as a data URL with the type of the actual image.
+pub fn image_data_url(value: &str, max_size: usize) -> Result<String, String> {
+    let base64 = match value.strip_prefix("data:") {
+        Some(url) => {
+            let (media_type, data) = url
+                .split_once(";base64,")
+                .ok_or("only base64 data URLs are supported")?;
+            if !media_type.starts_with("image/") {
+                return Err(format!("'{}' is not an image type", media_type));
+            }
+            data
+        }
+        None => value,
+    };
+
+    // Checked before decoding so huge values aren't decoded at all
+    if base64.len() / 4 * 3 > max_size {
+        return Err(format!("larger than {} bytes", max_size));
+    }
+    let data = STANDARD
+        .decode(base64.trim())
+        .map_err(|e| format!("invalid base64: {}", e))?;
+
+    let (_, media_type) = IMAGE_SIGNATURES
+        .iter()
+        .find(|(signature, media_type)| {
+            data.starts_with(signature)
+                && (*media_type != "image/webp" || data.get(8..12) == Some(b"WEBP"))
+        })
+        .ok_or("not a PNG, GIF, JPEG or WebP image")?;
+    Ok(format!("data:{};base64,{}", media_type, base64.trim()))
+}
@@ -1,3 +1,13 @@
 pub mod content_type;
+pub mod data_url;
+pub mod percent_encode;
+pub mod range;
+pub mod safe_path;
+pub mod slug;
 
 pub use content_type::*;
+pub use data_url::*;
+pub use percent_encode::*;
+pub use range::*;
+pub use safe_path::*;
+pub use slug::*;
@@ -0,0 +1,49 @@
+use std::ops::Range;
+
+/// Part of a file asked for with a `Range` header.
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// No usable range, send the whole file
+    Full,
+    /// Send these bytes with 206 Partial Content
+    Partial(Range<usize>),
+    /// The range starts past the end of the file, answer 416
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a file of `len` bytes. Only single byte
+/// ranges are supported, anything else gets the whole file.
+pub fn byte_range(header: Option<&str>, len: usize) -> ByteRange {
+    let Some(spec) = header.and_then(|header| header.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        // The last `suffix` bytes
+        ("", suffix) => match suffix.parse::<usize>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, "") => match start.parse() {
+            Ok(start) => (start, len),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, end) => match (start.parse::<usize>(), end.parse::<usize>()) {
+            // The end is inclusive, and may be past the end of the file
+            (Ok(start), Ok(end)) if start <= end => (start, end.saturating_add(1).min(len)),
+            _ => return ByteRange::Full,
+        },
+    };
+
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start..end)
+}
@@ -0,0 +1,35 @@
+/// Whether a request path is a plain relative path that can't escape the
+/// directory it is resolved against.
+pub fn is_safe_path(path: &str) -> bool {
+    !path.starts_with(['/', '\\'])
+        && !path.contains('\\')
+        && !path.contains(':')
+        && path.split('/').all(|segment| segment != "..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_are_safe() {
+        assert!(is_safe_path("pikachu.png"));
+        assert!(is_safe_path("gen1/pikachu.png"));
+    }
+
+    #[test]
+    fn escaping_paths_are_rejected() {
+        for path in [
+            "..",
+            "../team.txt",
+            "a/../b",
+            "/etc/passwd",
+            "\\server\\share",
+            "a\\..\\b",
+            "C:",
+            "C:/Windows",
+        ] {
+            assert!(!is_safe_path(path), "{}", path);
+        }
+    }
+}
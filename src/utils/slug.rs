@@ -0,0 +1,53 @@
+/// PokeAPI-style slug for a Pokemon name, used to match sprite files.
+/// `Mr. Mime` becomes `mr-mime`, `Nidoran♀` becomes `nidoran-f` and
+/// `Deoxys (Attack)` becomes `deoxys-attack`.
+pub fn slug(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        match c {
+            'a'..='z' | '0'..='9' => slug.push(c),
+            'é' | 'è' | 'ê' => slug.push('e'),
+            '♀' | '♂' => {
+                if !slug.is_empty() && !slug.ends_with('-') {
+                    slug.push('-');
+                }
+                slug.push(if c == '♀' { 'f' } else { 'm' });
+            }
+            // Word separators, collapsed into a single hyphen
+            ' ' | '-' | '_' | '(' | ')' if !slug.is_empty() && !slug.ends_with('-') => {
+                slug.push('-')
+            }
+            // Punctuation like `.`, `'` and `:` is dropped
+            _ => {}
+        }
+    }
+    slug.truncate(slug.trim_end_matches('-').len());
+    slug
+}
+
+/// Whether a name only uses characters found in Pokemon names, so it can
+/// match a sprite. Stray tabs or control characters usually mean a
+/// malformed line.
+pub fn is_plausible_name(name: &str) -> bool {
+    !slug(name).is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || " -_.'’:()♀♂%".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_with_punctuation() {
+        for (name, expected) in [
+            ("Mr. Mime", "mr-mime"),
+            ("Farfetch'd", "farfetchd"),
+            ("Nidoran♀", "nidoran-f"),
+            ("Deoxys (Attack)", "deoxys-attack"),
+        ] {
+            assert_eq!(slug(name), expected, "{}", name);
+        }
+    }
+}
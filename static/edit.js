@@ -0,0 +1,149 @@
+// Defaults used until /config has loaded
+let config = { team_size: 6 };
+let teams = {};
+let dragged = null;
+
+const teamSelectEl = document.getElementById("teamSelect");
+const tokenInputEl = document.getElementById("tokenInput");
+const pokemonListEl = document.getElementById("pokemonList");
+const addButtonEl = document.getElementById("addButton");
+const saveButtonEl = document.getElementById("saveButton");
+const statusEl = document.getElementById("status");
+
+// Remembered so it only has to be typed once per browser
+tokenInputEl.value = localStorage.getItem("token") || "";
+tokenInputEl.addEventListener("change", () => {
+  localStorage.setItem("token", tokenInputEl.value);
+});
+
+function setStatus(text) {
+  statusEl.textContent = text;
+}
+
+function addRow(pokemon) {
+  const row = document.createElement("li");
+  row.className = "pokemon-row";
+  row.draggable = true;
+  // Fields the editor doesn't show are saved back unchanged
+  row.pokemon = pokemon;
+
+  const handle = document.createElement("span");
+  handle.className = "handle";
+  handle.textContent = "☰";
+
+  const nameInput = document.createElement("input");
+  nameInput.className = "name";
+  nameInput.placeholder = "Pokemon";
+  nameInput.value = pokemon.name || "";
+
+  const nicknameInput = document.createElement("input");
+  nicknameInput.className = "nickname";
+  nicknameInput.placeholder = "Nickname";
+  nicknameInput.value = pokemon.nickname || "";
+
+  const removeButton = document.createElement("button");
+  removeButton.type = "button";
+  removeButton.textContent = "✕";
+  removeButton.addEventListener("click", () => row.remove());
+
+  row.addEventListener("dragstart", () => {
+    dragged = row;
+    row.classList.add("dragging");
+  });
+  row.addEventListener("dragend", () => {
+    dragged = null;
+    row.classList.remove("dragging");
+  });
+  row.addEventListener("dragover", (event) => {
+    event.preventDefault();
+    if (!dragged || dragged === row) return;
+    // Drop above or below depending on which half of the row is hovered
+    const rect = row.getBoundingClientRect();
+    const after = event.clientY > rect.top + rect.height / 2;
+    row.parentNode.insertBefore(dragged, after ? row.nextSibling : row);
+  });
+
+  row.append(handle, nameInput, nicknameInput, removeButton);
+  pokemonListEl.appendChild(row);
+}
+
+function showTeam(name) {
+  pokemonListEl.innerHTML = "";
+  // Padding slots have no name and are added back by the server
+  for (const pokemon of teams[name]?.pokemon ?? []) {
+    if (pokemon.name) addRow(pokemon);
+  }
+  setStatus("");
+}
+
+function currentTeam() {
+  const pokemon = [];
+  for (const row of pokemonListEl.children) {
+    const name = row.querySelector(".name").value.trim();
+    const nickname = row.querySelector(".nickname").value.trim();
+    if (!name) continue;
+    // Leave out what the server fills in. Types are looked up again for a
+    // different Pokemon.
+    const { sprite_url, suspicious_name, empty, types, ...stored } = row.pokemon;
+    if (name === row.pokemon.name && types) stored.types = types;
+    pokemon.push({ ...stored, name, nickname: nickname || null });
+  }
+  return { pokemon };
+}
+
+async function save() {
+  const name = teamSelectEl.value;
+  const headers = { "Content-Type": "application/json" };
+  if (tokenInputEl.value) {
+    headers.Authorization = `Bearer ${tokenInputEl.value}`;
+  }
+
+  setStatus("Saving...");
+  try {
+    const response = await fetch(`/teams/${encodeURIComponent(name)}`, {
+      method: "POST",
+      headers,
+      body: JSON.stringify(currentTeam()),
+    });
+    if (response.ok) {
+      setStatus("Saved");
+    } else {
+      const body = await response.json().catch(() => ({}));
+      setStatus(`Failed to save: ${body.error || response.status}`);
+    }
+  } catch (error) {
+    setStatus(`Failed to save: ${error}`);
+  }
+}
+
+async function load() {
+  try {
+    config = await (await fetch("/config")).json();
+  } catch (error) {
+    console.error("Error loading config:", error);
+  }
+
+  teams = await (await fetch("/teams")).json();
+  // With --single-team the only team is sent at the root, ask for its name
+  if (Array.isArray(teams.pokemon)) {
+    const [name] = await (await fetch("/teams/_names")).json();
+    teams = { [name ?? "team"]: teams };
+  }
+  for (const name of Object.keys(teams).sort()) {
+    const option = document.createElement("option");
+    option.value = name;
+    option.textContent = name;
+    teamSelectEl.appendChild(option);
+  }
+  showTeam(teamSelectEl.value);
+}
+
+teamSelectEl.addEventListener("change", () => showTeam(teamSelectEl.value));
+addButtonEl.addEventListener("click", () => {
+  if (pokemonListEl.children.length < config.team_size) {
+    addRow({ name: "" });
+  }
+});
+saveButtonEl.addEventListener("click", save);
+
+load().catch((error) => setStatus(`Failed to load teams: ${error}`));
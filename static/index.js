@@ -1,8 +1,14 @@
 let ws;
+let teams = {};
+// Server time (unix millis) the teams were last updated
+let updatedAt = null;
+// Defaults used until /config has loaded
+let config = { team_size: 6, padded: true, sprites_url: "/sprites", team_param: "team" };
 const teamGridEl = document.getElementById("teamGrid");
 
 const params = new URLSearchParams(window.location.search);
-const teamName = params.get("team") || "team";
+// Set once /config has said which search param selects the team
+let teamName = "team";
 
 function connect() {
   const protocol = window.location.protocol === "https:" ? "wss:" : "ws:";
@@ -10,12 +16,38 @@ function connect() {
 
   ws.onopen = () => {
     console.log("Connected to server");
+
+    // Only the selected team is rendered, so skip updates for the others
+    ws.send(JSON.stringify({ subscribe: teamName }));
   };
 
   ws.onmessage = (event) => {
     try {
       const data = JSON.parse(event.data);
-      updateTeam(data[teamName]?.pokemon ?? []);
+      switch (data.type) {
+        case "full_sync":
+          // Every team, sent on connect. With --single-team the only team is
+          // sent at the root instead.
+          teams = data.teams ?? { [teamName]: data };
+          break;
+        case "delta":
+          // A single team file changed
+          teams[data.team] = data.data;
+          break;
+        case "reload_sprite":
+          // A sprite file changed on the server, so skip the cached image
+          reloadSprite(data.name);
+          return;
+        case "error":
+          // The server couldn't read the team files
+          showError(data.message);
+          return;
+        default:
+          // Sent by a newer server, nothing to do with it here
+          return;
+      }
+      updatedAt = data.updated_at;
+      updateTeam(teams[teamName]?.pokemon ?? []);
     } catch (error) {
       console.error("Error parsing message:", error);
     }
@@ -33,37 +65,82 @@ function connect() {
   };
 }
 
+// Teams can have their own sprite directory on the server
+function spritesUrl() {
+  return config.team_sprites_urls?.[teamName] ?? config.sprites_url;
+}
+
+function reloadSprite(name) {
+  for (const img of teamGridEl.querySelectorAll("img")) {
+    // Sprites embedded in the team file don't come from the sprites directory
+    if (img.alt.toLowerCase() === name.toLowerCase() && !img.src.startsWith("data:")) {
+      img.src = `${spritesUrl()}/${img.alt}.png?v=${Date.now()}`;
+      img.style.display = "";
+      img.parentElement.classList.remove("empty");
+    }
+  }
+}
+
+const STAT_NAMES = { hp: "HP", atk: "Atk", def: "Def", spa: "SpA", spd: "SpD", spe: "Spe" };
+
+function formatStats(stats) {
+  return Object.entries(STAT_NAMES)
+    .filter(([key]) => stats[key] != null)
+    .map(([key, label]) => `${stats[key]} ${label}`)
+    .join(" / ");
+}
+
+// Tooltip with the full set, for team files that include one
+function describe(pokemon) {
+  const lines = [pokemon.item ? `${pokemon.name} @ ${pokemon.item}` : pokemon.name];
+  if (pokemon.ability) lines.push(`Ability: ${pokemon.ability}`);
+  if (pokemon.level) lines.push(`Level: ${pokemon.level}`);
+  if (pokemon.evs) lines.push(`EVs: ${formatStats(pokemon.evs)}`);
+  if (pokemon.nature) lines.push(`${pokemon.nature} Nature`);
+  if (pokemon.ivs) lines.push(`IVs: ${formatStats(pokemon.ivs)}`);
+  for (const move of pokemon.moves ?? []) lines.push(`- ${move}`);
+  return lines.join("\n");
+}
+
+function showError(message) {
+  const errorEl = document.createElement("div");
+  errorEl.className = "error";
+  errorEl.textContent = message;
+  teamGridEl.replaceChildren(errorEl);
+}
+
 function updateTeam(pokemon) {
   teamGridEl.innerHTML = "";
 
-  for (let i = 0; i < 6; i++) {
-    const pokemonName = pokemon[i].name || "";
-    const pokemonNickname = pokemon[i].nickname || "";
-    const isEmpty = !pokemonName;
+  for (let i = 0; i < pokemon.length; i++) {
+    const pokemonName = pokemon[i]?.name || "";
+    const pokemonNickname = pokemon[i]?.nickname || "";
+    // Padding slots are flagged by the server
+    const isEmpty = pokemon[i]?.empty || !pokemonName;
+
+    const fainted = pokemon[i]?.status === "FNT";
 
     const card = document.createElement("div");
-    card.className = `pokemon-card ${isEmpty ? "empty" : ""} fade-in`;
+    card.className = `pokemon-card ${isEmpty ? "empty" : ""} ${fainted ? "fainted" : ""} fade-in`;
     card.style.animationDelay = `${i * 0.1}s`;
+    // For custom CSS, e.g. `.pokemon-card[data-types~="fire"]`
+    if (pokemon[i]?.types?.length) {
+      card.dataset.types = pokemon[i].types.join(" ");
+    }
 
     const spriteContainer = document.createElement("div");
     spriteContainer.className = `sprite-container ${isEmpty ? "empty" : ""}`;
 
     if (!isEmpty) {
       const img = document.createElement("img");
-      // Try multiple common sprite naming conventions
-      img.src = `/sprites/${pokemonName}.png`;
+      // Embedded in the team file, or resolved by the server which falls
+      // back to a placeholder
+      img.src =
+        pokemon[i].sprite_data ?? pokemon[i].sprite_url ?? `${spritesUrl()}/${pokemonName}.png`;
       img.alt = pokemonName;
       img.onerror = function () {
-        // Try with different extensions or formats if PNG fails
-        if (this.src.endsWith(".png")) {
-          this.src = `/sprites/${pokemonName}.gif`;
-        } else if (this.src.endsWith(".gif")) {
-          this.src = `/sprites/${pokemonName}.jpg`;
-        } else {
-          // If all formats fail, show placeholder
-          this.style.display = "none";
-          spriteContainer.classList.add("empty");
-        }
+        this.style.display = "none";
+        spriteContainer.classList.add("empty");
       };
       spriteContainer.appendChild(img);
     }
@@ -74,11 +151,44 @@ function updateTeam(pokemon) {
       ? "Empty Slot"
       : pokemonNickname || pokemonName;
 
+    if (!isEmpty) {
+      card.title = describe(pokemon[i]);
+    }
+
     card.appendChild(spriteContainer);
     card.appendChild(nameEl);
+
+    const { hp_current: hpCurrent, hp_max: hpMax, status } = pokemon[i] ?? {};
+    if (hpMax) {
+      const fraction = Math.max(0, Math.min(1, hpCurrent / hpMax));
+      const hpBar = document.createElement("div");
+      hpBar.className = "hp-bar";
+      const hpFill = document.createElement("div");
+      hpFill.className = `hp-bar-fill ${fraction <= 0.25 ? "low" : ""}`;
+      hpFill.style.width = `${fraction * 100}%`;
+      hpBar.appendChild(hpFill);
+      card.appendChild(hpBar);
+    }
+    if (status) {
+      const statusEl = document.createElement("div");
+      statusEl.className = "status";
+      statusEl.textContent = status;
+      card.appendChild(statusEl);
+    }
     teamGridEl.appendChild(card);
   }
 }
 
-// Initialize connection
-connect();
+// Load the server settings, then connect
+fetch("/config")
+  .then((response) => response.json())
+  .then((serverConfig) => {
+    config = serverConfig;
+  })
+  .catch((error) => {
+    console.error("Error loading config:", error);
+  })
+  .finally(() => {
+    teamName = params.get(config.team_param) || "team";
+    connect();
+  });